@@ -0,0 +1,269 @@
+use crate::{
+    carchive::{self, archive_entry, OwnedEntry},
+    prelude::*,
+};
+
+use std::ffi::{CStr, CString};
+
+use libc::c_int;
+
+// permission bits for AclEntry::permset
+pub use crate::carchive::{
+    ARCHIVE_ENTRY_ACL_ADD_FILE, ARCHIVE_ENTRY_ACL_ADD_SUBDIRECTORY, ARCHIVE_ENTRY_ACL_APPEND_DATA,
+    ARCHIVE_ENTRY_ACL_DELETE, ARCHIVE_ENTRY_ACL_DELETE_CHILD, ARCHIVE_ENTRY_ACL_EXECUTE,
+    ARCHIVE_ENTRY_ACL_LIST_DIRECTORY, ARCHIVE_ENTRY_ACL_READ, ARCHIVE_ENTRY_ACL_READ_ACL,
+    ARCHIVE_ENTRY_ACL_READ_ATTRIBUTES, ARCHIVE_ENTRY_ACL_READ_DATA,
+    ARCHIVE_ENTRY_ACL_READ_NAMED_ATTRS, ARCHIVE_ENTRY_ACL_SYNCHRONIZE, ARCHIVE_ENTRY_ACL_WRITE,
+    ARCHIVE_ENTRY_ACL_WRITE_ACL, ARCHIVE_ENTRY_ACL_WRITE_ATTRIBUTES, ARCHIVE_ENTRY_ACL_WRITE_DATA,
+    ARCHIVE_ENTRY_ACL_WRITE_NAMED_ATTRS, ARCHIVE_ENTRY_ACL_WRITE_OWNER,
+};
+
+// NFSv4 inheritance flags, also part of AclEntry::permset
+pub use crate::carchive::{
+    ARCHIVE_ENTRY_ACL_ENTRY_DIRECTORY_INHERIT, ARCHIVE_ENTRY_ACL_ENTRY_FAILED_ACCESS,
+    ARCHIVE_ENTRY_ACL_ENTRY_FILE_INHERIT, ARCHIVE_ENTRY_ACL_ENTRY_INHERITED,
+    ARCHIVE_ENTRY_ACL_ENTRY_INHERIT_ONLY, ARCHIVE_ENTRY_ACL_ENTRY_NO_PROPAGATE_INHERIT,
+    ARCHIVE_ENTRY_ACL_ENTRY_SUCCESSFUL_ACCESS,
+};
+
+/// The two ACL families. A single ACL never mixes them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclBrand {
+    Posix1e,
+    Nfs4,
+}
+
+impl AclBrand {
+    fn code(&self) -> c_int {
+        match self {
+            AclBrand::Posix1e => carchive::ARCHIVE_ENTRY_ACL_TYPE_POSIX1E,
+            AclBrand::Nfs4 => carchive::ARCHIVE_ENTRY_ACL_TYPE_NFS4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclType {
+    /// POSIX.1e access ACL.
+    Access,
+    /// POSIX.1e default ACL, inherited by new entries of a directory.
+    Default,
+    Allow,
+    Deny,
+    Audit,
+    Alarm,
+}
+
+impl AclType {
+    fn code(&self) -> c_int {
+        match self {
+            AclType::Access => carchive::ARCHIVE_ENTRY_ACL_TYPE_ACCESS,
+            AclType::Default => carchive::ARCHIVE_ENTRY_ACL_TYPE_DEFAULT,
+            AclType::Allow => carchive::ARCHIVE_ENTRY_ACL_TYPE_ALLOW,
+            AclType::Deny => carchive::ARCHIVE_ENTRY_ACL_TYPE_DENY,
+            AclType::Audit => carchive::ARCHIVE_ENTRY_ACL_TYPE_AUDIT,
+            AclType::Alarm => carchive::ARCHIVE_ENTRY_ACL_TYPE_ALARM,
+        }
+    }
+
+    fn from_code(code: c_int) -> Option<AclType> {
+        match code {
+            carchive::ARCHIVE_ENTRY_ACL_TYPE_ACCESS => Some(AclType::Access),
+            carchive::ARCHIVE_ENTRY_ACL_TYPE_DEFAULT => Some(AclType::Default),
+            carchive::ARCHIVE_ENTRY_ACL_TYPE_ALLOW => Some(AclType::Allow),
+            carchive::ARCHIVE_ENTRY_ACL_TYPE_DENY => Some(AclType::Deny),
+            carchive::ARCHIVE_ENTRY_ACL_TYPE_AUDIT => Some(AclType::Audit),
+            carchive::ARCHIVE_ENTRY_ACL_TYPE_ALARM => Some(AclType::Alarm),
+            _ => None,
+        }
+    }
+
+    pub fn brand(&self) -> AclBrand {
+        match self {
+            AclType::Access | AclType::Default => AclBrand::Posix1e,
+            _ => AclBrand::Nfs4,
+        }
+    }
+}
+
+/// Whom an ACL entry applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclTag {
+    /// A user given by the qualifier.
+    User,
+    /// The file owner.
+    UserObj,
+    /// A group given by the qualifier.
+    Group,
+    /// The file group.
+    GroupObj,
+    Mask,
+    Other,
+    /// NFSv4 `everyone@`.
+    Everyone,
+}
+
+impl AclTag {
+    fn code(&self) -> c_int {
+        match self {
+            AclTag::User => carchive::ARCHIVE_ENTRY_ACL_USER,
+            AclTag::UserObj => carchive::ARCHIVE_ENTRY_ACL_USER_OBJ,
+            AclTag::Group => carchive::ARCHIVE_ENTRY_ACL_GROUP,
+            AclTag::GroupObj => carchive::ARCHIVE_ENTRY_ACL_GROUP_OBJ,
+            AclTag::Mask => carchive::ARCHIVE_ENTRY_ACL_MASK,
+            AclTag::Other => carchive::ARCHIVE_ENTRY_ACL_OTHER,
+            AclTag::Everyone => carchive::ARCHIVE_ENTRY_ACL_EVERYONE,
+        }
+    }
+
+    fn from_code(code: c_int) -> Option<AclTag> {
+        match code {
+            carchive::ARCHIVE_ENTRY_ACL_USER => Some(AclTag::User),
+            carchive::ARCHIVE_ENTRY_ACL_USER_OBJ => Some(AclTag::UserObj),
+            carchive::ARCHIVE_ENTRY_ACL_GROUP => Some(AclTag::Group),
+            carchive::ARCHIVE_ENTRY_ACL_GROUP_OBJ => Some(AclTag::GroupObj),
+            carchive::ARCHIVE_ENTRY_ACL_MASK => Some(AclTag::Mask),
+            carchive::ARCHIVE_ENTRY_ACL_OTHER => Some(AclTag::Other),
+            carchive::ARCHIVE_ENTRY_ACL_EVERYONE => Some(AclTag::Everyone),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclEntry {
+    pub acl_type: AclType,
+    pub tag: AclTag,
+    /// `ARCHIVE_ENTRY_ACL_*` permission bits, plus the inheritance flags for NFSv4.
+    pub permset: i32,
+    /// uid or gid of `User` and `Group` entries.
+    pub qualifier: Option<i32>,
+    /// user or group name of `User` and `Group` entries, preferred over the
+    /// qualifier when restoring.
+    pub name: Option<String>,
+}
+
+/// Access control list of an entry. The owner, group and other entries of a
+/// POSIX.1e access ACL mirror the permission bits and are only listed next to
+/// extended entries.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the usual text form, e.g. `user::rw-,user:1000:r--,group::r--,mask::r--,other::---`
+    /// or `owner@:rw-p--aARWcCos:-------:allow`.
+    pub fn from_text(text: &str, brand: AclBrand) -> Result<Acl> {
+        let entry = OwnedEntry::new();
+        let t = CString::new(text).unwrap();
+        // malformed entries are skipped with a warning, refuse them instead
+        match unsafe {
+            carchive::archive_entry_acl_from_text(entry.as_ptr(), t.as_ptr(), brand.code())
+        } {
+            carchive::ARCHIVE_OK => Ok(Acl::read(entry.as_ptr())),
+            _ => Err(Error::InvalidAcl(text.to_owned())),
+        }
+    }
+
+    /// Text form of the ACL, as understood by `from_text`.
+    pub fn to_text(&self) -> Result<String> {
+        let entry = OwnedEntry::new();
+        self.apply(entry.as_ptr())?;
+
+        unsafe {
+            let text = carchive::archive_entry_acl_to_text(entry.as_ptr(), std::ptr::null_mut(), 0);
+            if text.is_null() {
+                return Ok(String::new());
+            }
+            let result = String::from_utf8_lossy(CStr::from_ptr(text).to_bytes()).into();
+            libc::free(text as *mut libc::c_void);
+            Ok(result)
+        }
+    }
+
+    pub fn entries(&self) -> &[AclEntry] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, entry: AclEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn brand(&self) -> Option<AclBrand> {
+        self.entries.first().map(|e| e.acl_type.brand())
+    }
+
+    pub(crate) fn read(entry: *mut archive_entry) -> Acl {
+        let mut acl = Acl::new();
+        unsafe {
+            // asking for both brands at once would also list the POSIX.1e
+            // owner/group/other entries next to NFSv4 ones
+            let want = if carchive::archive_entry_acl_types(entry)
+                & carchive::ARCHIVE_ENTRY_ACL_TYPE_NFS4
+                != 0
+            {
+                carchive::ARCHIVE_ENTRY_ACL_TYPE_NFS4
+            } else {
+                carchive::ARCHIVE_ENTRY_ACL_TYPE_POSIX1E
+            };
+
+            carchive::archive_entry_acl_reset(entry, want);
+            let (mut acl_type, mut permset, mut tag, mut qualifier) = (0, 0, 0, 0);
+            let mut name = std::ptr::null();
+            while carchive::archive_entry_acl_next(
+                entry,
+                want,
+                &mut acl_type,
+                &mut permset,
+                &mut tag,
+                &mut qualifier,
+                &mut name,
+            ) == carchive::ARCHIVE_OK
+            {
+                let (Some(acl_type), Some(tag)) =
+                    (AclType::from_code(acl_type), AclTag::from_code(tag))
+                else {
+                    continue;
+                };
+                acl.push(AclEntry {
+                    acl_type,
+                    tag,
+                    permset,
+                    qualifier: (qualifier >= 0).then_some(qualifier),
+                    name: (!name.is_null())
+                        .then(|| String::from_utf8_lossy(CStr::from_ptr(name).to_bytes()).into()),
+                });
+            }
+        }
+        acl
+    }
+
+    pub(crate) fn apply(&self, entry: *mut archive_entry) -> Result<()> {
+        for e in &self.entries {
+            let name = e.name.as_ref().map(|n| CString::new(n.as_str()).unwrap());
+            match unsafe {
+                carchive::archive_entry_acl_add_entry(
+                    entry,
+                    e.acl_type.code(),
+                    e.permset,
+                    e.tag.code(),
+                    e.qualifier.unwrap_or(-1),
+                    name.as_ref().map_or(std::ptr::null(), |n| n.as_ptr()),
+                )
+            } {
+                carchive::ARCHIVE_OK => (),
+                _ => return Err(Error::InvalidAcl(format!("{:?}", e))),
+            }
+        }
+        Ok(())
+    }
+}
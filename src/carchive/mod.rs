@@ -4,7 +4,7 @@ use std::ffi::CStr;
 
 pub use generated::*;
 
-use crate::Metadata;
+use crate::{acl::Acl, Metadata};
 
 fn entry_pathname(entry: *mut archive_entry) -> String {
     let pathname = unsafe { archive_entry_pathname(entry) };
@@ -59,6 +59,94 @@ fn entry_perm(entry: *mut archive_entry) -> mode_t{
     unsafe{archive_entry_perm(entry)}
 }
 
+fn entry_string(value: *const std::os::raw::c_char) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(unsafe { CStr::from_ptr(value) }.to_bytes()).into())
+}
+
+fn entry_uname(entry: *mut archive_entry) -> Option<String> {
+    entry_string(unsafe { archive_entry_uname(entry) })
+}
+
+fn entry_gname(entry: *mut archive_entry) -> Option<String> {
+    entry_string(unsafe { archive_entry_gname(entry) })
+}
+
+fn entry_symlink(entry: *mut archive_entry) -> Option<String> {
+    entry_string(unsafe { archive_entry_symlink(entry) })
+}
+
+fn entry_hardlink(entry: *mut archive_entry) -> Option<String> {
+    entry_string(unsafe { archive_entry_hardlink(entry) })
+}
+
+fn entry_nlink(entry: *mut archive_entry) -> u32 {
+    unsafe { archive_entry_nlink(entry) }
+}
+
+fn entry_dev(entry: *mut archive_entry) -> dev_t {
+    unsafe { archive_entry_dev(entry) }
+}
+
+fn entry_ino(entry: *mut archive_entry) -> i64 {
+    unsafe { archive_entry_ino64(entry) }
+}
+
+fn entry_rdevmajor(entry: *mut archive_entry) -> dev_t {
+    unsafe { archive_entry_rdevmajor(entry) }
+}
+
+fn entry_rdevminor(entry: *mut archive_entry) -> dev_t {
+    unsafe { archive_entry_rdevminor(entry) }
+}
+
+fn entry_birthtime(entry: *mut archive_entry) -> Option<(i64, i64)> {
+    unsafe {
+        if archive_entry_birthtime_is_set(entry) == 0 {
+            return None;
+        }
+        Some((archive_entry_birthtime(entry), archive_entry_birthtime_nsec(entry)))
+    }
+}
+
+fn entry_xattrs(entry: *mut archive_entry) -> Vec<(String, Vec<u8>)> {
+    let mut xattrs = Vec::new();
+    unsafe {
+        archive_entry_xattr_reset(entry);
+        let mut name = std::ptr::null();
+        let mut value = std::ptr::null();
+        let mut size = 0;
+        while archive_entry_xattr_next(entry, &mut name, &mut value, &mut size) == ARCHIVE_OK {
+            let value = if value.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(value as *const u8, size).to_vec()
+            };
+            xattrs.push((entry_string(name).unwrap_or_default(), value));
+        }
+    }
+    xattrs
+}
+
+fn entry_sparse(entry: *mut archive_entry) -> Vec<(i64, i64)> {
+    let mut sparse = Vec::new();
+    unsafe {
+        archive_entry_sparse_reset(entry);
+        let (mut offset, mut length) = (0, 0);
+        while archive_entry_sparse_next(entry, &mut offset, &mut length) == ARCHIVE_OK {
+            sparse.push((offset, length));
+        }
+    }
+    sparse
+}
+
+fn entry_encrypted(entry: *mut archive_entry) -> bool {
+    unsafe { archive_entry_is_encrypted(entry) != 0 }
+}
+
 impl From<*mut archive_entry> for Metadata {
     fn from(input: *mut archive_entry) -> Self {
         Metadata {
@@ -74,6 +162,56 @@ impl From<*mut archive_entry> for Metadata {
             mtime_nano: entry_mtime_nano(input),
             owner: entry_owner(input),
             group: entry_group(input),
+            uname: entry_uname(input),
+            gname: entry_gname(input),
+            symlink: entry_symlink(input),
+            hardlink: entry_hardlink(input),
+            nlink: entry_nlink(input),
+            dev: entry_dev(input),
+            ino: entry_ino(input),
+            rdevmajor: entry_rdevmajor(input),
+            rdevminor: entry_rdevminor(input),
+            birthtime: entry_birthtime(input),
+            xattrs: entry_xattrs(input),
+            acl: Acl::read(input),
+            sparse: entry_sparse(input),
+            encrypted: entry_encrypted(input),
         }
     }
 }
+
+// archive_entry allocated on the rust side, freed when dropped so early
+// returns on error paths do not leak it
+pub(crate) struct OwnedEntry {
+    entry: *mut archive_entry,
+}
+
+impl OwnedEntry {
+    pub(crate) fn new() -> Self {
+        OwnedEntry {
+            entry: unsafe { archive_entry_new() },
+        }
+    }
+
+    // takes ownership of an entry handed over by libarchive
+    pub(crate) fn from_raw(entry: *mut archive_entry) -> Self {
+        OwnedEntry { entry }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut archive_entry {
+        self.entry
+    }
+
+    // gives up ownership, whoever gets the pointer must free it
+    pub(crate) fn into_raw(self) -> *mut archive_entry {
+        let entry = self.entry;
+        std::mem::forget(self);
+        entry
+    }
+}
+
+impl Drop for OwnedEntry {
+    fn drop(&mut self) {
+        unsafe { archive_entry_free(self.entry) };
+    }
+}
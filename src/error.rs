@@ -24,8 +24,35 @@ pub enum Error {
     #[error("Unknown format")]
     UnknownFormat,
 
+    #[error("Unsupported archive format: '{0}'")]
+    UnsupportedFormat(String),
+
     #[error("Error to create the archive struct, is null")]
     NullArchive,
+
+    #[error("Refusing to extract unsafe path '{0}'")]
+    UnsafePath(String),
+
+    #[error("Format '{0}' can only be read from a seekable source")]
+    SeekRequired(String),
+
+    #[error("Wrong or missing passphrase: '{0}'")]
+    Passphrase(String),
+
+    #[error("Encryption is not supported by the output format")]
+    EncryptionUnsupported,
+
+    #[error("Invalid ACL: '{0}'")]
+    InvalidAcl(String),
+
+    #[error("Data of entry '{0}' does not match its declared size of {1} bytes")]
+    SizeMismatch(String, i64),
+
+    #[error("Invalid archive index: '{0}'")]
+    InvalidIndex(String),
+
+    #[error("Entry '{0}' can't be stored in this format: '{1}'")]
+    Unstorable(String, String),
 }
 
 impl From<*mut carchive::archive> for Error {
@@ -51,8 +78,19 @@ impl From<*mut carchive::archive> for Error {
     }
 }
 
+// raw message of the last libarchive error, to be embedded in a more specific variant
+pub(crate) fn error_message(input: *mut carchive::archive) -> String {
+    unsafe {
+        let error_string = carchive::archive_error_string(input);
+        if error_string.is_null() {
+            return "unknown error".to_owned();
+        }
+        CStr::from_ptr(error_string).to_string_lossy().to_string()
+    }
+}
+
 impl From<Error> for io::Error {
     fn from(value: Error) -> Self {
-        io::Error::new(io::ErrorKind::Other, value)
+        io::Error::other(value)
     }
 }
@@ -0,0 +1,280 @@
+use crate::{
+    carchive::{self, archive, archive_entry},
+    prelude::*,
+};
+
+use std::{
+    ffi::{CStr, CString},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path},
+};
+
+use libc::c_int;
+
+/// Controls how entries are restored on disk by `ArchiveReader::extract_to`.
+#[derive(Clone, Debug)]
+pub struct ExtractOptions {
+    permissions: bool,
+    times: bool,
+    owner: bool,
+    overwrite: bool,
+    unlink: bool,
+    xattrs: bool,
+    acls: bool,
+    secure_symlinks: bool,
+    secure_nodotdot: bool,
+    secure_noabsolutepaths: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            permissions: true,
+            times: true,
+            owner: false,
+            overwrite: true,
+            unlink: false,
+            xattrs: false,
+            acls: false,
+            secure_symlinks: true,
+            secure_nodotdot: true,
+            secure_noabsolutepaths: true,
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restore the permission bits stored in the archive.
+    pub fn permissions(mut self, enable: bool) -> Self {
+        self.permissions = enable;
+        self
+    }
+
+    /// Restore modification and access times.
+    pub fn times(mut self, enable: bool) -> Self {
+        self.times = enable;
+        self
+    }
+
+    /// Restore uid/gid. Usually only works when running as root.
+    pub fn owner(mut self, enable: bool) -> Self {
+        self.owner = enable;
+        self
+    }
+
+    /// Replace files already present in the destination.
+    pub fn overwrite(mut self, enable: bool) -> Self {
+        self.overwrite = enable;
+        self
+    }
+
+    /// Unlink existing files before creating them instead of writing over them.
+    pub fn unlink(mut self, enable: bool) -> Self {
+        self.unlink = enable;
+        self
+    }
+
+    /// Restore extended attributes, e.g. file capabilities. Some namespaces
+    /// need root.
+    pub fn xattrs(mut self, enable: bool) -> Self {
+        self.xattrs = enable;
+        self
+    }
+
+    /// Restore POSIX.1e or NFSv4 ACLs, when the filesystem supports them.
+    pub fn acls(mut self, enable: bool) -> Self {
+        self.acls = enable;
+        self
+    }
+
+    /// Refuse to extract through symlinks already present on disk.
+    pub fn secure_symlinks(mut self, enable: bool) -> Self {
+        self.secure_symlinks = enable;
+        self
+    }
+
+    /// Refuse entries containing `..` path components.
+    pub fn secure_nodotdot(mut self, enable: bool) -> Self {
+        self.secure_nodotdot = enable;
+        self
+    }
+
+    /// Refuse entries with absolute paths. When disabled, absolute entries are
+    /// written to their absolute location instead of below the destination.
+    pub fn secure_noabsolutepaths(mut self, enable: bool) -> Self {
+        self.secure_noabsolutepaths = enable;
+        self
+    }
+
+    pub(crate) fn flags(&self) -> c_int {
+        let mut flags = 0;
+        if self.permissions {
+            flags |= carchive::ARCHIVE_EXTRACT_PERM;
+        }
+        if self.times {
+            flags |= carchive::ARCHIVE_EXTRACT_TIME;
+        }
+        if self.owner {
+            flags |= carchive::ARCHIVE_EXTRACT_OWNER;
+        }
+        if !self.overwrite {
+            flags |= carchive::ARCHIVE_EXTRACT_NO_OVERWRITE;
+        }
+        if self.unlink {
+            flags |= carchive::ARCHIVE_EXTRACT_UNLINK;
+        }
+        if self.xattrs {
+            flags |= carchive::ARCHIVE_EXTRACT_XATTR;
+        }
+        if self.acls {
+            flags |= carchive::ARCHIVE_EXTRACT_ACL;
+        }
+        if self.secure_symlinks {
+            flags |= carchive::ARCHIVE_EXTRACT_SECURE_SYMLINKS;
+        }
+        if self.secure_nodotdot {
+            flags |= carchive::ARCHIVE_EXTRACT_SECURE_NODOTDOT;
+        }
+        // ARCHIVE_EXTRACT_SECURE_NOABSOLUTEPATHS is never handed to libarchive:
+        // every path is rebased below the (absolute) destination, so it is
+        // enforced by check_entry on the original entry path instead
+        flags
+    }
+}
+
+// owned archive_write_disk handle
+pub(crate) struct DiskWriter {
+    archive: *mut archive,
+}
+
+impl DiskWriter {
+    pub(crate) fn new(options: &ExtractOptions) -> Result<Self> {
+        let archive = unsafe { carchive::archive_write_disk_new() };
+        if archive.is_null() {
+            return Err(Error::NullArchive);
+        }
+
+        let writer = DiskWriter { archive };
+
+        match unsafe { carchive::archive_write_disk_set_options(archive, options.flags()) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+            _ => return Err(Error::from(archive)),
+        }
+
+        match unsafe { carchive::archive_write_disk_set_standard_lookup(archive) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+            _ => return Err(Error::from(archive)),
+        }
+
+        Ok(writer)
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut archive {
+        self.archive
+    }
+
+    // directory permissions and times are only applied on close, so errors
+    // there must be reported back to the caller
+    pub(crate) fn close(self) -> Result<()> {
+        match unsafe { carchive::archive_write_close(self.archive) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+            _ => Err(Error::from(self.archive)),
+        }
+    }
+}
+
+impl Drop for DiskWriter {
+    fn drop(&mut self) {
+        unsafe { carchive::archive_write_free(self.archive) };
+    }
+}
+
+fn check_path(name: &[u8], dest: &Path, options: &ExtractOptions) -> Result<()> {
+    let path = Path::new(std::ffi::OsStr::from_bytes(name));
+    let unsafe_path = || Error::UnsafePath(String::from_utf8_lossy(name).into());
+
+    if options.secure_noabsolutepaths && path.is_absolute() {
+        return Err(unsafe_path());
+    }
+
+    if options.secure_nodotdot && path.components().any(|c| c == Component::ParentDir) {
+        return Err(unsafe_path());
+    }
+
+    if options.secure_symlinks && !path.is_absolute() {
+        // only the parents matter, a symlink in the last component is
+        // replaced by the extracted entry
+        let mut current = dest.to_path_buf();
+        let mut components = path.components().peekable();
+        while let Some(component) = components.next() {
+            if components.peek().is_none() {
+                break;
+            }
+            current.push(component);
+            if let Ok(meta) = current.symlink_metadata() {
+                if meta.file_type().is_symlink() {
+                    return Err(unsafe_path());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// applies the policy to the entry path and its hardlink target before anything
+// touches the disk
+pub(crate) fn check_entry(
+    entry: *mut archive_entry,
+    dest: &Path,
+    options: &ExtractOptions,
+) -> Result<()> {
+    unsafe {
+        let pathname = carchive::archive_entry_pathname(entry);
+        if !pathname.is_null() {
+            check_path(CStr::from_ptr(pathname).to_bytes(), dest, options)?;
+        }
+
+        let hardlink = carchive::archive_entry_hardlink(entry);
+        if !hardlink.is_null() {
+            check_path(CStr::from_ptr(hardlink).to_bytes(), dest, options)?;
+        }
+    }
+    Ok(())
+}
+
+fn rebased(dest: &Path, name: &[u8]) -> CString {
+    let mut out = dest.as_os_str().as_bytes().to_vec();
+    out.push(b'/');
+    out.extend_from_slice(name);
+    CString::new(out).unwrap()
+}
+
+// archive_write_disk resolves paths against the process working directory,
+// so the entry path (and hardlink target) is moved below the destination.
+// Absolute paths only get here when the policy allows them and are kept as is.
+pub(crate) fn rebase_entry(entry: *mut archive_entry, dest: &Path) {
+    unsafe {
+        let pathname = carchive::archive_entry_pathname(entry);
+        if !pathname.is_null() {
+            let name = CStr::from_ptr(pathname).to_bytes();
+            if !name.starts_with(b"/") {
+                let p = rebased(dest, name);
+                carchive::archive_entry_copy_pathname(entry, p.as_ptr());
+            }
+        }
+
+        let hardlink = carchive::archive_entry_hardlink(entry);
+        if !hardlink.is_null() {
+            let name = CStr::from_ptr(hardlink).to_bytes();
+            if !name.starts_with(b"/") {
+                let h = rebased(dest, name);
+                carchive::archive_entry_copy_hardlink(entry, h.as_ptr());
+            }
+        }
+    }
+}
@@ -0,0 +1,480 @@
+use crate::{
+    carchive::{self, archive},
+    prelude::*,
+};
+
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+    str::FromStr,
+};
+
+use libc::c_int;
+
+// commented out in the generated bindings
+const ARCHIVE_FORMAT_AR: c_int = 0x70000;
+
+/// Archive formats known by libarchive. Some of them can only be read (rar, cab, ...)
+/// and some only written (shar).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    SevenZip,
+    Ar,
+    ArBsd,
+    ArSvr4,
+    Cab,
+    Cpio,
+    CpioBin,
+    CpioNewc,
+    CpioOdc,
+    CpioPwb,
+    Empty,
+    Iso9660,
+    Lha,
+    Mtree,
+    Rar,
+    Rar5,
+    Raw,
+    Shar,
+    SharDump,
+    /// Generic tar. Written as restricted pax, like bsdtar does.
+    Tar,
+    GnuTar,
+    Pax,
+    PaxRestricted,
+    Ustar,
+    V7Tar,
+    Warc,
+    Xar,
+    Zip,
+}
+
+/// Compression and encoding filters known by libarchive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Filter {
+    None,
+    Bzip2,
+    Compress,
+    Grzip,
+    Gzip,
+    Lrzip,
+    Lz4,
+    Lzip,
+    Lzma,
+    Lzop,
+    Rpm,
+    Uu,
+    Xz,
+    Zstd,
+}
+
+impl Format {
+    /// Maps an `ARCHIVE_FORMAT_*` code as reported by libarchive.
+    pub fn from_code(code: c_int) -> Option<Format> {
+        let format = match code {
+            carchive::ARCHIVE_FORMAT_CPIO => Format::Cpio,
+            carchive::ARCHIVE_FORMAT_CPIO_POSIX => Format::CpioOdc,
+            carchive::ARCHIVE_FORMAT_CPIO_BIN_LE | carchive::ARCHIVE_FORMAT_CPIO_BIN_BE => {
+                Format::CpioBin
+            }
+            carchive::ARCHIVE_FORMAT_CPIO_SVR4_NOCRC | carchive::ARCHIVE_FORMAT_CPIO_SVR4_CRC => {
+                Format::CpioNewc
+            }
+            carchive::ARCHIVE_FORMAT_CPIO_AFIO_LARGE => Format::Cpio,
+            carchive::ARCHIVE_FORMAT_CPIO_PWB => Format::CpioPwb,
+            carchive::ARCHIVE_FORMAT_SHAR | carchive::ARCHIVE_FORMAT_SHAR_BASE => Format::Shar,
+            carchive::ARCHIVE_FORMAT_SHAR_DUMP => Format::SharDump,
+            carchive::ARCHIVE_FORMAT_TAR => Format::Tar,
+            carchive::ARCHIVE_FORMAT_TAR_USTAR => Format::Ustar,
+            carchive::ARCHIVE_FORMAT_TAR_PAX_INTERCHANGE => Format::Pax,
+            carchive::ARCHIVE_FORMAT_TAR_PAX_RESTRICTED => Format::PaxRestricted,
+            carchive::ARCHIVE_FORMAT_TAR_GNUTAR => Format::GnuTar,
+            carchive::ARCHIVE_FORMAT_ISO9660 | carchive::ARCHIVE_FORMAT_ISO9660_ROCKRIDGE => {
+                Format::Iso9660
+            }
+            carchive::ARCHIVE_FORMAT_ZIP => Format::Zip,
+            carchive::ARCHIVE_FORMAT_EMPTY => Format::Empty,
+            ARCHIVE_FORMAT_AR => Format::Ar,
+            carchive::ARCHIVE_FORMAT_AR_GNU => Format::ArSvr4,
+            carchive::ARCHIVE_FORMAT_AR_BSD => Format::ArBsd,
+            carchive::ARCHIVE_FORMAT_MTREE => Format::Mtree,
+            carchive::ARCHIVE_FORMAT_RAW => Format::Raw,
+            carchive::ARCHIVE_FORMAT_XAR => Format::Xar,
+            carchive::ARCHIVE_FORMAT_LHA => Format::Lha,
+            carchive::ARCHIVE_FORMAT_CAB => Format::Cab,
+            carchive::ARCHIVE_FORMAT_RAR => Format::Rar,
+            carchive::ARCHIVE_FORMAT_7ZIP => Format::SevenZip,
+            carchive::ARCHIVE_FORMAT_WARC => Format::Warc,
+            carchive::ARCHIVE_FORMAT_RAR_V5 => Format::Rar5,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Name used by `archive_write_set_format_by_name`. `Tar` has no entry there.
+    pub(crate) fn write_name(&self) -> &'static str {
+        match self {
+            Format::Tar => "paxr",
+            other => other.name(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Format::SevenZip => "7zip",
+            Format::Ar => "ar",
+            Format::ArBsd => "arbsd",
+            Format::ArSvr4 => "arsvr4",
+            Format::Cab => "cab",
+            Format::Cpio => "cpio",
+            Format::CpioBin => "bin",
+            Format::CpioNewc => "newc",
+            Format::CpioOdc => "odc",
+            Format::CpioPwb => "pwb",
+            Format::Empty => "empty",
+            Format::Iso9660 => "iso9660",
+            Format::Lha => "lha",
+            Format::Mtree => "mtree",
+            Format::Rar => "rar",
+            Format::Rar5 => "rar5",
+            Format::Raw => "raw",
+            Format::Shar => "shar",
+            Format::SharDump => "shardump",
+            Format::Tar => "tar",
+            Format::GnuTar => "gnutar",
+            Format::Pax => "pax",
+            Format::PaxRestricted => "paxr",
+            Format::Ustar => "ustar",
+            Format::V7Tar => "v7tar",
+            Format::Warc => "warc",
+            Format::Xar => "xar",
+            Format::Zip => "zip",
+        }
+    }
+
+    // enables this format on a reader that has not been opened yet
+    pub(crate) fn read_support(&self, archive: *mut archive) -> Result<()> {
+        let r = unsafe {
+            match self {
+                Format::SevenZip => carchive::archive_read_support_format_7zip(archive),
+                Format::Ar | Format::ArBsd | Format::ArSvr4 => {
+                    carchive::archive_read_support_format_ar(archive)
+                }
+                Format::Cab => carchive::archive_read_support_format_cab(archive),
+                Format::Cpio
+                | Format::CpioBin
+                | Format::CpioNewc
+                | Format::CpioOdc
+                | Format::CpioPwb => carchive::archive_read_support_format_cpio(archive),
+                Format::Empty => carchive::archive_read_support_format_empty(archive),
+                Format::Iso9660 => carchive::archive_read_support_format_iso9660(archive),
+                Format::Lha => carchive::archive_read_support_format_lha(archive),
+                Format::Mtree => carchive::archive_read_support_format_mtree(archive),
+                Format::Rar => carchive::archive_read_support_format_rar(archive),
+                Format::Rar5 => carchive::archive_read_support_format_rar5(archive),
+                Format::Raw => carchive::archive_read_support_format_raw(archive),
+                // libarchive cannot read shell archives back
+                Format::Shar | Format::SharDump => return Err(Error::UnknownFormat),
+                Format::Tar
+                | Format::GnuTar
+                | Format::Pax
+                | Format::PaxRestricted
+                | Format::Ustar
+                | Format::V7Tar => carchive::archive_read_support_format_tar(archive),
+                Format::Warc => carchive::archive_read_support_format_warc(archive),
+                Format::Xar => carchive::archive_read_support_format_xar(archive),
+                Format::Zip => carchive::archive_read_support_format_zip(archive),
+            }
+        };
+
+        match r {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+            _ => Err(Error::from(archive)),
+        }
+    }
+}
+
+impl Format {
+    // libarchive can write this format. Tried on an archive of its own, a writer
+    // that is given a format it has no writer for can't be used anymore.
+    pub(crate) fn write_support(&self) -> Result<()> {
+        let name = CString::new(self.write_name()).unwrap();
+        unsafe {
+            let archive = carchive::archive_write_new();
+            let r = carchive::archive_write_set_format_by_name(archive, name.as_ptr());
+            let supported = write_check(archive, r, Error::UnknownFormat);
+            carchive::archive_write_free(archive);
+            supported
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let format = match s {
+            "7zip" => Format::SevenZip,
+            "ar" => Format::Ar,
+            "arbsd" => Format::ArBsd,
+            "arsvr4" | "argnu" => Format::ArSvr4,
+            "cab" => Format::Cab,
+            "cpio" => Format::Cpio,
+            "bin" => Format::CpioBin,
+            "newc" => Format::CpioNewc,
+            "odc" => Format::CpioOdc,
+            "pwb" => Format::CpioPwb,
+            "empty" => Format::Empty,
+            "iso9660" | "iso" | "cd9660" => Format::Iso9660,
+            "lha" => Format::Lha,
+            "mtree" => Format::Mtree,
+            "rar" => Format::Rar,
+            "rar5" => Format::Rar5,
+            "raw" => Format::Raw,
+            "shar" => Format::Shar,
+            "shardump" => Format::SharDump,
+            "tar" => Format::Tar,
+            "gnutar" => Format::GnuTar,
+            "pax" | "posix" => Format::Pax,
+            "paxr" | "rpax" | "bsdtar" => Format::PaxRestricted,
+            "ustar" => Format::Ustar,
+            "v7tar" | "v7" | "oldtar" => Format::V7Tar,
+            "warc" => Format::Warc,
+            "xar" => Format::Xar,
+            "zip" => Format::Zip,
+            _ => return Err(Error::UnknownFormat),
+        };
+        Ok(format)
+    }
+}
+
+impl Filter {
+    /// Maps an `ARCHIVE_FILTER_*` code as reported by libarchive.
+    pub fn from_code(code: c_int) -> Option<Filter> {
+        let filter = match code {
+            carchive::ARCHIVE_FILTER_NONE => Filter::None,
+            carchive::ARCHIVE_FILTER_GZIP => Filter::Gzip,
+            carchive::ARCHIVE_FILTER_BZIP2 => Filter::Bzip2,
+            carchive::ARCHIVE_FILTER_COMPRESS => Filter::Compress,
+            carchive::ARCHIVE_FILTER_LZMA => Filter::Lzma,
+            carchive::ARCHIVE_FILTER_XZ => Filter::Xz,
+            carchive::ARCHIVE_FILTER_UU => Filter::Uu,
+            carchive::ARCHIVE_FILTER_RPM => Filter::Rpm,
+            carchive::ARCHIVE_FILTER_LZIP => Filter::Lzip,
+            carchive::ARCHIVE_FILTER_LRZIP => Filter::Lrzip,
+            carchive::ARCHIVE_FILTER_LZOP => Filter::Lzop,
+            carchive::ARCHIVE_FILTER_GRZIP => Filter::Grzip,
+            carchive::ARCHIVE_FILTER_LZ4 => Filter::Lz4,
+            carchive::ARCHIVE_FILTER_ZSTD => Filter::Zstd,
+            _ => return None,
+        };
+        Some(filter)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Filter::None => "none",
+            Filter::Bzip2 => "bzip2",
+            Filter::Compress => "compress",
+            Filter::Grzip => "grzip",
+            Filter::Gzip => "gzip",
+            Filter::Lrzip => "lrzip",
+            Filter::Lz4 => "lz4",
+            Filter::Lzip => "lzip",
+            Filter::Lzma => "lzma",
+            Filter::Lzop => "lzop",
+            Filter::Rpm => "rpm",
+            Filter::Uu => "uuencode",
+            Filter::Xz => "xz",
+            Filter::Zstd => "zstd",
+        }
+    }
+
+    // highest compression-level accepted by the filter, None when it has no levels
+    pub(crate) fn max_compression_level(&self) -> Option<u32> {
+        match self {
+            Filter::Bzip2
+            | Filter::Gzip
+            | Filter::Lrzip
+            | Filter::Xz
+            | Filter::Lz4
+            | Filter::Lzip
+            | Filter::Lzma
+            | Filter::Lzop => Some(9),
+            Filter::Zstd => Some(22),
+            _ => None,
+        }
+    }
+
+    // enables this filter on a reader that has not been opened yet
+    pub(crate) fn read_support(&self, archive: *mut archive) -> Result<()> {
+        let r = unsafe {
+            match self {
+                Filter::None => carchive::archive_read_support_filter_none(archive),
+                Filter::Bzip2 => carchive::archive_read_support_filter_bzip2(archive),
+                Filter::Compress => carchive::archive_read_support_filter_compress(archive),
+                Filter::Grzip => carchive::archive_read_support_filter_grzip(archive),
+                Filter::Gzip => carchive::archive_read_support_filter_gzip(archive),
+                Filter::Lrzip => carchive::archive_read_support_filter_lrzip(archive),
+                Filter::Lz4 => carchive::archive_read_support_filter_lz4(archive),
+                Filter::Lzip => carchive::archive_read_support_filter_lzip(archive),
+                Filter::Lzma => carchive::archive_read_support_filter_lzma(archive),
+                Filter::Lzop => carchive::archive_read_support_filter_lzop(archive),
+                Filter::Rpm => carchive::archive_read_support_filter_rpm(archive),
+                Filter::Uu => carchive::archive_read_support_filter_uu(archive),
+                Filter::Xz => carchive::archive_read_support_filter_xz(archive),
+                Filter::Zstd => carchive::archive_read_support_filter_zstd(archive),
+            }
+        };
+
+        match r {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+            _ => Err(Error::from(archive)),
+        }
+    }
+}
+
+impl Filter {
+    // libarchive can write this filter, tried like Format::write_support
+    pub(crate) fn write_support(&self) -> Result<()> {
+        // "none" is not a name known by archive_write_add_filter_by_name
+        if *self == Filter::None {
+            return Ok(());
+        }
+        let name = CString::new(self.name()).unwrap();
+        unsafe {
+            let archive = carchive::archive_write_new();
+            let r = carchive::archive_write_add_filter_by_name(archive, name.as_ptr());
+            let supported = write_check(archive, r, Error::UnknownFilter);
+            carchive::archive_write_free(archive);
+            supported
+        }
+    }
+}
+
+// unknown is returned when libarchive has no writer for the name
+unsafe fn write_check(archive: *mut archive, r: c_int, unknown: Error) -> Result<()> {
+    match r {
+        carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+        _ if carchive::archive_errno(archive) == libc::EINVAL => Err(unknown),
+        _ => Err(Error::from(archive)),
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let filter = match s {
+            "none" => Filter::None,
+            "bzip2" => Filter::Bzip2,
+            "compress" => Filter::Compress,
+            "grzip" => Filter::Grzip,
+            "gzip" => Filter::Gzip,
+            "lrzip" => Filter::Lrzip,
+            "lz4" => Filter::Lz4,
+            "lzip" => Filter::Lzip,
+            "lzma" => Filter::Lzma,
+            "lzop" => Filter::Lzop,
+            "rpm" => Filter::Rpm,
+            "uu" | "uuencode" => Filter::Uu,
+            "xz" => Filter::Xz,
+            "zstd" => Filter::Zstd,
+            _ => return Err(Error::UnknownFilter),
+        };
+        Ok(filter)
+    }
+}
+
+/// Archive format of an opened archive, as detected by libarchive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormatInfo {
+    code: c_int,
+    name: String,
+}
+
+/// One filter (compression or encoding layer) of an opened archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterInfo {
+    code: c_int,
+    name: String,
+}
+
+impl FormatInfo {
+    /// One of the `ARCHIVE_FORMAT_*` codes, including the variant bits (e.g. GNU tar).
+    pub fn code(&self) -> c_int {
+        self.code
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Typed format, `None` for codes this crate does not know about.
+    pub fn format(&self) -> Option<Format> {
+        Format::from_code(self.code)
+    }
+}
+
+impl FilterInfo {
+    /// One of the `ARCHIVE_FILTER_*` codes.
+    pub fn code(&self) -> c_int {
+        self.code
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Typed filter, `None` for external programs and unknown codes.
+    pub fn filter(&self) -> Option<Filter> {
+        Filter::from_code(self.code)
+    }
+}
+
+fn lossy_name(name: *const libc::c_char) -> String {
+    String::from_utf8_lossy(unsafe { CStr::from_ptr(name) }.to_bytes()).into()
+}
+
+pub(crate) fn archive_format(archive: *mut archive) -> Result<FormatInfo> {
+    // the format is only known once the first header has been read
+    let name = unsafe { carchive::archive_format_name(archive) };
+    if name.is_null() {
+        return Err(Error::UnknownFormat);
+    }
+
+    Ok(FormatInfo {
+        code: unsafe { carchive::archive_format(archive) },
+        name: lossy_name(name),
+    })
+}
+
+pub(crate) fn archive_filters(archive: *mut archive) -> Vec<FilterInfo> {
+    let count = unsafe { carchive::archive_filter_count(archive) };
+
+    (0..count)
+        .map(|i| {
+            let name = unsafe { carchive::archive_filter_name(archive, i) };
+            FilterInfo {
+                code: unsafe { carchive::archive_filter_code(archive, i) },
+                name: if name.is_null() {
+                    "".to_owned()
+                } else {
+                    lossy_name(name)
+                },
+            }
+        })
+        .collect()
+}
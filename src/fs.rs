@@ -0,0 +1,232 @@
+use crate::{index::ArchiveIndex, prelude::*, reader::ArchiveReader, Metadata, AE_IFDIR, AE_IFLNK};
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{Error as IOError, ErrorKind, Read, Seek},
+};
+
+// symlinks followed by open before giving up, same as Linux
+const MAX_SYMLINKS: usize = 40;
+
+/// Read-only view of an archive as a directory tree. Paths are relative to the
+/// root of the archive, `./` prefixes and trailing slashes of the entries are
+/// dropped, and directories missing from the archive are made up from the paths
+/// of their content.
+///
+/// The headers are read once when it is created. Data is only read by `open`,
+/// which jumps straight to the entry for zip and uncompressed tar and cpio archives.
+pub struct ArchiveFs<R: Read + Seek> {
+    reader: ArchiveReader<R>,
+    index: ArchiveIndex,
+    nodes: HashMap<String, Node>,
+}
+
+struct Node {
+    meta: Metadata,
+    // path in the archive, None for directories that are not stored
+    archivepath: Option<String>,
+    children: BTreeSet<String>,
+}
+
+/// Data of a file opened with `ArchiveFs::open`.
+pub struct ArchiveFile<'a, R: Read + Seek> {
+    reader: &'a mut ArchiveReader<R>,
+    meta: &'a Metadata,
+}
+
+impl<R: Read + Seek> ArchiveFs<R> {
+    pub fn new(source: R) -> Result<Self> {
+        ArchiveFs::from_reader(ArchiveReader::new(source)?)
+    }
+
+    /// Uses a reader configured through `ArchiveReader::builder`, e.g. with a
+    /// passphrase.
+    pub fn from_reader(mut reader: ArchiveReader<R>) -> Result<Self> {
+        let (index, entries) = reader.scan_headers()?;
+
+        let mut nodes = HashMap::new();
+        nodes.insert(String::new(), Node::implicit(String::new()));
+        for mut meta in entries {
+            let archivepath = meta.filepath().to_owned();
+            let path = normalize(&archivepath);
+            if path.is_empty() {
+                // "./" only describes the root itself
+                continue;
+            }
+
+            add_parents(&mut nodes, &path);
+            meta.filepath = path.clone();
+            let node = nodes
+                .entry(path)
+                .or_insert_with_key(|p| Node::implicit(p.clone()));
+            // a path stored twice is replaced by its last copy, like on extraction
+            node.meta = meta;
+            node.archivepath = Some(archivepath);
+        }
+
+        Ok(ArchiveFs {
+            reader,
+            index,
+            nodes,
+        })
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.nodes.contains_key(&normalize(path))
+    }
+
+    /// Metadata of the entry at `path`. Symlinks are not followed.
+    pub fn metadata(&self, path: &str) -> Result<&Metadata> {
+        Ok(&self.node(&normalize(path))?.meta)
+    }
+
+    /// Entries directly below the directory at `path`, sorted by name. `""` or
+    /// `"/"` is the root of the archive.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<&Metadata>> {
+        let path = normalize(path);
+        let node = self.node(&path)?;
+        if node.meta.nodetype() != AE_IFDIR {
+            return Err(IOError::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a directory inside archive", path),
+            )
+            .into());
+        }
+
+        Ok(node
+            .children
+            .iter()
+            .map(|name| &self.nodes[&join(&path, name)].meta)
+            .collect())
+    }
+
+    /// Opens the data of the file at `path`. Symlinks and hardlinks are followed
+    /// within the archive.
+    pub fn open(&mut self, path: &str) -> Result<ArchiveFile<'_, R>> {
+        let path = self.resolve(normalize(path))?;
+        let node = &self.nodes[&path];
+        if node.meta.nodetype() == AE_IFDIR {
+            return Err(IOError::new(
+                ErrorKind::InvalidInput,
+                format!("{} is a directory inside archive", path),
+            )
+            .into());
+        }
+
+        // only directories are made up, anything else has an archive path
+        let archivepath = node.archivepath.as_deref().unwrap();
+        self.reader.seek_index(&self.index, archivepath)?;
+        Ok(ArchiveFile {
+            reader: &mut self.reader,
+            meta: &node.meta,
+        })
+    }
+
+    pub fn into_reader(self) -> ArchiveReader<R> {
+        self.reader
+    }
+
+    fn node(&self, path: &str) -> Result<&Node> {
+        self.nodes.get(path).ok_or_else(|| {
+            IOError::new(
+                ErrorKind::NotFound,
+                format!("path {} doesn't exist inside archive", path),
+            )
+            .into()
+        })
+    }
+
+    // path of the entry holding the data of path
+    fn resolve(&self, mut path: String) -> Result<String> {
+        for _ in 0..MAX_SYMLINKS {
+            let meta = &self.node(&path)?.meta;
+            path = match (meta.symlink(), meta.hardlink()) {
+                (Some(target), _) if meta.nodetype() == AE_IFLNK => {
+                    if target.starts_with('/') {
+                        normalize(target)
+                    } else {
+                        normalize(&join(parent(&path), target))
+                    }
+                }
+                // the data of a hardlink is stored with its first copy
+                (_, Some(target)) if meta.size() == 0 => normalize(target),
+                _ => return Ok(path),
+            };
+        }
+
+        Err(IOError::new(
+            ErrorKind::InvalidInput,
+            format!("too many levels of symbolic links for {}", path),
+        )
+        .into())
+    }
+}
+
+impl Node {
+    fn implicit(path: String) -> Self {
+        let mut meta = Metadata::new(0o755);
+        meta.nodetype = AE_IFDIR;
+        meta.filepath = path;
+        Node {
+            meta,
+            archivepath: None,
+            children: BTreeSet::new(),
+        }
+    }
+}
+
+impl<R: Read + Seek> ArchiveFile<'_, R> {
+    pub fn metadata(&self) -> &Metadata {
+        self.meta
+    }
+}
+
+impl<R: Read + Seek> Read for ArchiveFile<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+// links path to its parent, creating every missing directory up to the root
+fn add_parents(nodes: &mut HashMap<String, Node>, path: &str) {
+    let mut child = path;
+    loop {
+        let dir = parent(child);
+        let name = &child[child.rfind('/').map_or(0, |i| i + 1)..];
+        let node = nodes
+            .entry(dir.to_owned())
+            .or_insert_with(|| Node::implicit(dir.to_owned()));
+        if !node.children.insert(name.to_owned()) || dir.is_empty() {
+            // the parent was already linked to its own parents
+            return;
+        }
+        child = dir;
+    }
+}
+
+// relative path without empty, "." and ".." components, "" for the root
+fn normalize(path: &str) -> String {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+fn parent(path: &str) -> &str {
+    path.rfind('/').map_or("", |i| &path[..i])
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
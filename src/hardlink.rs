@@ -0,0 +1,58 @@
+use crate::carchive::{self, archive, archive_entry_linkresolver, OwnedEntry};
+
+use std::ptr::null_mut;
+
+// owned archive_entry_linkresolver. Entries need dev, ino and nlink set to be
+// recognised as links of each other.
+pub(crate) struct LinkResolver {
+    resolver: *mut archive_entry_linkresolver,
+}
+
+impl LinkResolver {
+    // the strategy depends on the output format: tar stores the data with the
+    // first link, new cpio with the last one so some entries are held back
+    pub(crate) fn new(writer: *mut archive) -> Self {
+        unsafe {
+            let resolver = carchive::archive_entry_linkresolver_new();
+            carchive::archive_entry_linkresolver_set_strategy(
+                resolver,
+                carchive::archive_format(writer),
+            );
+            LinkResolver { resolver }
+        }
+    }
+
+    // returns the entries to write now, in order. Either one can be missing when
+    // the resolver holds the entry back.
+    pub(crate) fn linkify(
+        &mut self,
+        entry: OwnedEntry,
+    ) -> (Option<OwnedEntry>, Option<OwnedEntry>) {
+        let mut e = entry.into_raw();
+        let mut f = null_mut();
+        unsafe { carchive::archive_entry_linkify(self.resolver, &mut e, &mut f) };
+        (owned(e), owned(f))
+    }
+
+    // entries still held back, to be written before the archive is closed
+    pub(crate) fn deferred(&mut self) -> Option<OwnedEntry> {
+        let mut e = null_mut();
+        let mut f = null_mut();
+        unsafe { carchive::archive_entry_linkify(self.resolver, &mut e, &mut f) };
+        owned(e)
+    }
+}
+
+fn owned(entry: *mut carchive::archive_entry) -> Option<OwnedEntry> {
+    if entry.is_null() {
+        None
+    } else {
+        Some(OwnedEntry::from_raw(entry))
+    }
+}
+
+impl Drop for LinkResolver {
+    fn drop(&mut self) {
+        unsafe { carchive::archive_entry_linkresolver_free(self.resolver) };
+    }
+}
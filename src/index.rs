@@ -0,0 +1,276 @@
+use crate::{format::Format, prelude::*};
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"SAINDEX1";
+// end of central directory record of a zip, without its comment
+const ZIP_EOCD_SIZE: u64 = 22;
+
+/// Positions of the entries of an archive, built once with
+/// `ArchiveReader::build_index` and then used by `ArchiveReader::seek_index`.
+///
+/// Uncompressed tar and cpio archives are jumped into directly at the header of the
+/// entry, zip archives at the local header their central directory points to. Other
+/// formats and anything compressed still read the headers from the start up to the
+/// entry.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveIndex {
+    format: Option<Format>,
+    direct: bool,
+    // archive order, a path stored twice keeps the position of its last copy
+    entries: Vec<(String, i64)>,
+    lookup: HashMap<String, usize>,
+}
+
+impl ArchiveIndex {
+    pub(crate) fn new(format: Option<Format>, direct: bool) -> Self {
+        ArchiveIndex {
+            format,
+            direct,
+            entries: Vec::new(),
+            lookup: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, path: String, position: i64) {
+        self.lookup.insert(path.clone(), self.entries.len());
+        self.entries.push((path, position));
+    }
+
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+
+    /// True when entries can be reached without reading the headers before them.
+    pub fn is_direct(&self) -> bool {
+        self.direct
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Paths in archive order.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(path, _)| path.as_str())
+    }
+
+    /// Offset of the header of `path` in the uncompressed archive data, or of its
+    /// local header in a zip.
+    pub fn position(&self, path: &str) -> Option<i64> {
+        self.lookup.get(path).map(|&i| self.entries[i].1)
+    }
+
+    pub fn write_to<W: Write>(&self, mut dest: W) -> Result<()> {
+        let format = self.format.map(|f| f.to_string()).unwrap_or_default();
+        dest.write_all(MAGIC)?;
+        dest.write_all(&[self.direct as u8])?;
+        write_bytes(&mut dest, format.as_bytes())?;
+        dest.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for (path, position) in &self.entries {
+            write_bytes(&mut dest, path.as_bytes())?;
+            dest.write_all(&position.to_le_bytes())?;
+        }
+        dest.flush()?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut source: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        read_exact(&mut source, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidIndex("not an archive index".to_owned()));
+        }
+
+        let mut direct = [0u8; 1];
+        read_exact(&mut source, &mut direct)?;
+        let format = match read_string(&mut source)? {
+            name if name.is_empty() => None,
+            name => Some(name.parse::<Format>()?),
+        };
+
+        let mut index = ArchiveIndex::new(format, direct[0] != 0);
+        for _ in 0..read_u64(&mut source)? {
+            let path = read_string(&mut source)?;
+            let position = read_u64(&mut source)? as i64;
+            index.push(path, position);
+        }
+        Ok(index)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        ArchiveIndex::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+// formats where every entry starts with a header that can be read on its own
+pub(crate) fn jumpable(format: Format) -> bool {
+    matches!(
+        format,
+        Format::Tar
+            | Format::GnuTar
+            | Format::Pax
+            | Format::PaxRestricted
+            | Format::Ustar
+            | Format::V7Tar
+            | Format::Cpio
+            | Format::CpioBin
+            | Format::CpioNewc
+            | Format::CpioOdc
+            | Format::CpioPwb
+    )
+}
+
+// offsets of the local headers of a zip by path, from its central directory.
+// None when there is no central directory to read.
+pub(crate) fn zip_local_headers<R: Read + Seek>(
+    source: &mut R,
+) -> Result<Option<HashMap<String, i64>>> {
+    let len = source.seek(SeekFrom::End(0))?;
+    let tail_len = len.min(ZIP_EOCD_SIZE + 0xffff);
+    let mut tail = vec![0u8; tail_len as usize];
+    source.seek(SeekFrom::Start(len - tail_len))?;
+    source.read_exact(&mut tail)?;
+
+    // the end of central directory record is followed by a comment of at most 64 KiB
+    let found = (0..tail.len().saturating_sub(ZIP_EOCD_SIZE as usize - 1))
+        .rev()
+        .find(|&i| tail[i..].starts_with(b"PK\x05\x06"));
+    let Some(at) = found else {
+        return Ok(None);
+    };
+    let eocd = &tail[at..];
+    let mut cd_end = len - tail_len + at as u64;
+    let mut cd_size = le32(eocd, 12) as u64;
+    let mut cd_offset = le32(eocd, 16) as u64;
+
+    // zip64 keeps the real values in a record of its own, right before its locator
+    if cd_end >= 20 + 56 {
+        let mut zip64 = [0u8; 56 + 20];
+        source.seek(SeekFrom::Start(cd_end - zip64.len() as u64))?;
+        source.read_exact(&mut zip64)?;
+        if zip64.starts_with(b"PK\x06\x06") && zip64[56..].starts_with(b"PK\x06\x07") {
+            cd_end -= zip64.len() as u64;
+            cd_size = le64(&zip64, 40);
+            cd_offset = le64(&zip64, 48);
+        }
+    }
+    if cd_size > cd_end {
+        return Ok(None);
+    }
+
+    // a prefix like a self-extracting stub moves every offset by the same amount
+    let cd_start = cd_end - cd_size;
+    let shift = cd_start as i64 - cd_offset as i64;
+    let mut cd = vec![0u8; cd_size as usize];
+    source.seek(SeekFrom::Start(cd_start))?;
+    source.read_exact(&mut cd)?;
+
+    let mut headers = HashMap::new();
+    let mut rest = cd.as_slice();
+    while !rest.is_empty() {
+        if rest.len() < 46 || !rest.starts_with(b"PK\x01\x02") {
+            return Ok(None);
+        }
+        let name_len = le16(rest, 28) as usize;
+        let extra_len = le16(rest, 30) as usize;
+        let comment_len = le16(rest, 32) as usize;
+        let record_len = 46 + name_len + extra_len + comment_len;
+        if rest.len() < record_len {
+            return Ok(None);
+        }
+
+        let mut offset = le32(rest, 42) as u64;
+        if offset == 0xffffffff {
+            let extra = &rest[46 + name_len..46 + name_len + extra_len];
+            match zip64_offset(rest, extra) {
+                Some(o) => offset = o,
+                None => return Ok(None),
+            }
+        }
+        // names that are not UTF-8 are converted by libarchive, they can't be matched
+        let Ok(name) = String::from_utf8(rest[46..46 + name_len].to_vec()) else {
+            return Ok(None);
+        };
+        // a path stored twice is read from its last copy
+        let position = offset as i64 + shift;
+        let best = headers.entry(name).or_insert(position);
+        *best = position.max(*best);
+
+        rest = &rest[record_len..];
+    }
+    Ok(Some(headers))
+}
+
+// local header offset from the zip64 extra field, after the sizes it also holds
+fn zip64_offset(record: &[u8], mut extra: &[u8]) -> Option<u64> {
+    while extra.len() >= 4 {
+        let (id, size) = (le16(extra, 0), le16(extra, 2) as usize);
+        let data = extra.get(4..4 + size)?;
+        if id == 1 {
+            let skip = [24, 20]
+                .iter()
+                .filter(|&&at| le32(record, at) == 0xffffffff)
+                .count()
+                * 8;
+            return data.get(skip..skip + 8).map(|o| le64(o, 0));
+        }
+        extra = &extra[4 + size..];
+    }
+    None
+}
+
+fn le16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn le32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn le64(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+fn write_bytes<W: Write>(dest: &mut W, bytes: &[u8]) -> Result<()> {
+    dest.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    dest.write_all(bytes)?;
+    Ok(())
+}
+
+// a short read means the file was cut, not an io failure of the caller
+fn read_exact<R: Read>(source: &mut R, buf: &mut [u8]) -> Result<()> {
+    source.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => Error::InvalidIndex("truncated".to_owned()),
+        _ => e.into(),
+    })
+}
+
+fn read_u64<R: Read>(source: &mut R) -> Result<u64> {
+    let mut value = [0u8; 8];
+    read_exact(source, &mut value)?;
+    Ok(u64::from_le_bytes(value))
+}
+
+fn read_string<R: Read>(source: &mut R) -> Result<String> {
+    let len = read_u64(source)?;
+    let mut value = Vec::new();
+    source.take(len).read_to_end(&mut value)?;
+    if value.len() as u64 != len {
+        return Err(Error::InvalidIndex("truncated".to_owned()));
+    }
+    String::from_utf8(value).map_err(|_| Error::InvalidIndex("path is not UTF-8".to_owned()))
+}
@@ -1,12 +1,25 @@
 mod carchive;
 mod prelude;
 mod error;
+mod hardlink;
+mod passphrase;
+mod sparse;
+pub mod acl;
+pub mod extract;
+pub mod format;
+pub mod fs;
+pub mod index;
+pub mod matcher;
 pub mod reader;
+pub mod stream;
+pub mod tree;
 pub mod writer;
 
 use std::fs::Metadata as FSMeta;
-use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+#[derive(Clone, Debug)]
 pub struct Metadata {
     filepath: String,
     size: i64,
@@ -20,15 +33,38 @@ pub struct Metadata {
     mtime_nano: i64,
     owner: __uid_t,
     group: __gid_t,
+    uname: Option<String>,
+    gname: Option<String>,
+    symlink: Option<String>,
+    hardlink: Option<String>,
+    nlink: u32,
+    dev: dev_t,
+    ino: i64,
+    rdevmajor: dev_t,
+    rdevminor: dev_t,
+    birthtime: Option<(i64, i64)>,
+    xattrs: Vec<(String, Vec<u8>)>,
+    acl: Acl,
+    sparse: Vec<(i64, i64)>,
+    encrypted: bool,
 }
 
 fn into_nodetype(source: &FSMeta) -> u32 {
-    if source.is_dir() {
+    let filetype = source.file_type();
+    if filetype.is_dir() {
         AE_IFDIR
-    } else if source.is_file() {
+    } else if filetype.is_file() {
         AE_IFREG
-    } else if source.is_symlink() {
+    } else if filetype.is_symlink() {
         AE_IFLNK
+    } else if filetype.is_fifo() {
+        AE_IFIFO
+    } else if filetype.is_char_device() {
+        AE_IFCHR
+    } else if filetype.is_block_device() {
+        AE_IFBLK
+    } else if filetype.is_socket() {
+        AE_IFSOCK
     } else {
         0
     }
@@ -50,11 +86,65 @@ impl From<FSMeta> for Metadata {
             mtime_nano: meta.mtime_nsec(),
             owner: meta.uid(),
             group: meta.gid(),
+            uname: None,
+            gname: None,
+            symlink: None,
+            hardlink: None,
+            nlink: meta.nlink() as u32,
+            dev: meta.dev(),
+            ino: meta.ino() as i64,
+            rdevmajor: libc::major(meta.rdev()) as dev_t,
+            rdevminor: libc::minor(meta.rdev()) as dev_t,
+            birthtime: meta
+                .created()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| (d.as_secs() as i64, d.subsec_nanos() as i64)),
+            xattrs: Vec::new(),
+            acl: Acl::new(),
+            sparse: Vec::new(),
+            encrypted: false,
         }
     }
 }
 
 impl Metadata {
+    /// Metadata for a regular file not backed by anything on disk: owned by root
+    /// and timestamped now. The `add_*` methods of the writer override the type.
+    pub fn new(perm: mode_t) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Metadata {
+            filepath: "".to_owned(),
+            size: 0,
+            nodetype: AE_IFREG,
+            perm,
+            ctime: now.as_secs() as i64,
+            ctime_nano: now.subsec_nanos() as i64,
+            atime: now.as_secs() as i64,
+            atime_nano: now.subsec_nanos() as i64,
+            mtime: now.as_secs() as i64,
+            mtime_nano: now.subsec_nanos() as i64,
+            owner: 0,
+            group: 0,
+            uname: None,
+            gname: None,
+            symlink: None,
+            hardlink: None,
+            nlink: 1,
+            dev: 0,
+            ino: 0,
+            rdevmajor: 0,
+            rdevminor: 0,
+            birthtime: None,
+            xattrs: Vec::new(),
+            acl: Acl::new(),
+            sparse: Vec::new(),
+            encrypted: false,
+        }
+    }
+
     pub fn filepath(&self)-> &str{
         &self.filepath
     }
@@ -63,6 +153,11 @@ impl Metadata {
         self.size
     }
 
+    /// Declared size of the data, e.g. for `ArchiveWriter::start_entry`.
+    pub fn set_size(&mut self, size: i64) {
+        self.size = size;
+    }
+
     pub fn nodetype(&self) -> u32{
         self.nodetype
     }
@@ -102,9 +197,85 @@ impl Metadata {
     pub fn group(&self) -> __gid_t {
         self.group
     }
+
+    /// Owner name, when the archive stores one.
+    pub fn uname(&self) -> Option<&str> {
+        self.uname.as_deref()
+    }
+
+    /// Group name, when the archive stores one.
+    pub fn gname(&self) -> Option<&str> {
+        self.gname.as_deref()
+    }
+
+    /// Target of a symlink entry.
+    pub fn symlink(&self) -> Option<&str> {
+        self.symlink.as_deref()
+    }
+
+    /// Path of the entry this one is a hardlink to.
+    pub fn hardlink(&self) -> Option<&str> {
+        self.hardlink.as_deref()
+    }
+
+    pub fn nlink(&self) -> u32 {
+        self.nlink
+    }
+
+    pub fn dev(&self) -> dev_t {
+        self.dev
+    }
+
+    pub fn ino(&self) -> i64 {
+        self.ino
+    }
+
+    /// Major number of a device entry.
+    pub fn rdevmajor(&self) -> dev_t {
+        self.rdevmajor
+    }
+
+    /// Minor number of a device entry.
+    pub fn rdevminor(&self) -> dev_t {
+        self.rdevminor
+    }
+
+    /// Creation time, only known by some formats and filesystems.
+    pub fn birthtime(&self) -> Option<i64> {
+        self.birthtime.map(|(secs, _)| secs)
+    }
+
+    pub fn birthtime_nano(&self) -> Option<i64> {
+        self.birthtime.map(|(_, nanos)| nanos)
+    }
+
+    /// Extended attributes as (name, value) pairs.
+    pub fn xattrs(&self) -> &[(String, Vec<u8>)] {
+        &self.xattrs
+    }
+
+    pub fn acl(&self) -> &Acl {
+        &self.acl
+    }
+
+    /// ACL stored with the entry when written.
+    pub fn set_acl(&mut self, acl: Acl) {
+        self.acl = acl;
+    }
+
+    /// (offset, length) of the parts holding data in a sparse file, empty when
+    /// the whole entry is data.
+    pub fn sparse_map(&self) -> &[(i64, i64)] {
+        &self.sparse
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
 }
 
-use carchive::{__gid_t, __uid_t, mode_t};
+use acl::Acl;
+use carchive::{__gid_t, __uid_t, dev_t, mode_t};
 
 pub use error::Error;
 
@@ -0,0 +1,282 @@
+use crate::{
+    carchive::{self, __gid_t, __uid_t, archive, archive_entry},
+    prelude::*,
+};
+
+use std::{
+    ffi::{CStr, CString},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+use libc::c_int;
+
+/// Selects entries by path, like the `--include` / `--exclude` options of tar,
+/// by time or by owner. Given to `ArchiveReaderBuilder::matcher` or
+/// `ArchiveWriter::set_matcher`. An entry is kept when it passes every kind of
+/// condition that was set.
+///
+/// Patterns are shell globs matched against the path stored in the archive, where
+/// `*` also matches `/`. A pattern matching a directory matches everything below
+/// it too. Exclusions can match anywhere in the path (`*.log`, `target`),
+/// inclusions only from its start (`src/*.rs`). With at least one inclusion,
+/// entries matching none of them are left out.
+///
+/// Time conditions all have to hold, e.g. newer than one date and older than
+/// another. Owner conditions are grouped by kind, uid, gid, user name and group
+/// name: an entry has to match one of the values of every kind that was set.
+///
+/// When a tree is archived, directories matching an exclusion are not walked at
+/// all, like with tar. Inclusions and time or owner conditions never stop the walk,
+/// a directory they leave out can still have content that is kept.
+pub struct Matcher {
+    archive: *mut archive,
+    // the exclusions alone, to leave whole directories out of a walk
+    exclusions: *mut archive,
+}
+
+/// Timestamp of the entries compared by a time condition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeField {
+    Mtime,
+    Ctime,
+}
+
+/// Entries kept by a time condition, relative to the time it is given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeComparison {
+    Newer,
+    NewerOrEqual,
+    Older,
+    OlderOrEqual,
+    Equal,
+}
+
+fn time_flag(field: TimeField, comparison: TimeComparison) -> c_int {
+    let field = match field {
+        TimeField::Mtime => carchive::ARCHIVE_MATCH_MTIME,
+        TimeField::Ctime => carchive::ARCHIVE_MATCH_CTIME,
+    };
+    let comparison = match comparison {
+        TimeComparison::Newer => carchive::ARCHIVE_MATCH_NEWER,
+        TimeComparison::NewerOrEqual => {
+            carchive::ARCHIVE_MATCH_NEWER | carchive::ARCHIVE_MATCH_EQUAL
+        }
+        TimeComparison::Older => carchive::ARCHIVE_MATCH_OLDER,
+        TimeComparison::OlderOrEqual => {
+            carchive::ARCHIVE_MATCH_OLDER | carchive::ARCHIVE_MATCH_EQUAL
+        }
+        TimeComparison::Equal => carchive::ARCHIVE_MATCH_EQUAL,
+    };
+    field | comparison
+}
+
+impl Matcher {
+    pub fn new() -> Result<Self> {
+        let archive = unsafe { carchive::archive_match_new() };
+        if archive.is_null() {
+            return Err(Error::NullArchive);
+        }
+        let exclusions = unsafe { carchive::archive_match_new() };
+        if exclusions.is_null() {
+            unsafe { carchive::archive_match_free(archive) };
+            return Err(Error::NullArchive);
+        }
+        Ok(Matcher {
+            archive,
+            exclusions,
+        })
+    }
+
+    pub fn include(&mut self, pattern: &str) -> Result<()> {
+        let p = CString::new(pattern).unwrap();
+        let r = unsafe { carchive::archive_match_include_pattern(self.archive, p.as_ptr()) };
+        self.check(r)
+    }
+
+    pub fn exclude(&mut self, pattern: &str) -> Result<()> {
+        let p = CString::new(pattern).unwrap();
+        for archive in [self.archive, self.exclusions] {
+            let r = unsafe { carchive::archive_match_exclude_pattern(archive, p.as_ptr()) };
+            check(archive, r)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the inclusion patterns listed in a file, one per line or separated by
+    /// NUL bytes (as written by `find -print0`).
+    pub fn include_from_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        null_separated: bool,
+    ) -> Result<()> {
+        let p = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+        let r = unsafe {
+            carchive::archive_match_include_pattern_from_file(
+                self.archive,
+                p.as_ptr(),
+                null_separated as c_int,
+            )
+        };
+        self.check(r)
+    }
+
+    /// Adds the exclusion patterns listed in a file, like `include_from_file`.
+    pub fn exclude_from_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        null_separated: bool,
+    ) -> Result<()> {
+        let p = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+        for archive in [self.archive, self.exclusions] {
+            let r = unsafe {
+                carchive::archive_match_exclude_pattern_from_file(
+                    archive,
+                    p.as_ptr(),
+                    null_separated as c_int,
+                )
+            };
+            check(archive, r)?;
+        }
+        Ok(())
+    }
+
+    /// Keeps entries whose `field` compares to `sec` / `nsec` (seconds since the
+    /// epoch) as asked, e.g. `Newer` for everything modified after the last run.
+    pub fn include_time(
+        &mut self,
+        field: TimeField,
+        comparison: TimeComparison,
+        sec: i64,
+        nsec: i64,
+    ) -> Result<()> {
+        let r = unsafe {
+            carchive::archive_match_include_time(
+                self.archive,
+                time_flag(field, comparison),
+                sec,
+                nsec,
+            )
+        };
+        self.check(r)
+    }
+
+    /// Same as `include_time` with a date as understood by `tar --newer`, like
+    /// `"2024-03-01 12:00:00 UTC"`.
+    pub fn include_date(
+        &mut self,
+        field: TimeField,
+        comparison: TimeComparison,
+        date: &str,
+    ) -> Result<()> {
+        let d = CString::new(date).unwrap();
+        let r = unsafe {
+            carchive::archive_match_include_date(
+                self.archive,
+                time_flag(field, comparison),
+                d.as_ptr(),
+            )
+        };
+        self.check(r)
+    }
+
+    /// Same as `include_time` with the time `field` of the file at `path`.
+    pub fn include_file_time<P: AsRef<Path>>(
+        &mut self,
+        field: TimeField,
+        comparison: TimeComparison,
+        path: P,
+    ) -> Result<()> {
+        let p = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+        let r = unsafe {
+            carchive::archive_match_include_file_time(
+                self.archive,
+                time_flag(field, comparison),
+                p.as_ptr(),
+            )
+        };
+        self.check(r)
+    }
+
+    pub fn include_uid(&mut self, uid: __uid_t) -> Result<()> {
+        let r = unsafe { carchive::archive_match_include_uid(self.archive, uid as i64) };
+        self.check(r)
+    }
+
+    pub fn include_gid(&mut self, gid: __gid_t) -> Result<()> {
+        let r = unsafe { carchive::archive_match_include_gid(self.archive, gid as i64) };
+        self.check(r)
+    }
+
+    /// Keeps entries owned by the user `name`. Entries without an owner name never
+    /// match.
+    pub fn include_uname(&mut self, name: &str) -> Result<()> {
+        let n = CString::new(name).unwrap();
+        let r = unsafe { carchive::archive_match_include_uname(self.archive, n.as_ptr()) };
+        self.check(r)
+    }
+
+    /// Keeps entries of the group `name`. Entries without a group name never match.
+    pub fn include_gname(&mut self, name: &str) -> Result<()> {
+        let n = CString::new(name).unwrap();
+        let r = unsafe { carchive::archive_match_include_gname(self.archive, n.as_ptr()) };
+        self.check(r)
+    }
+
+    /// Inclusion patterns no entry matched so far, the "Not found in archive" of tar.
+    pub fn unmatched_inclusions(&mut self) -> Result<Vec<String>> {
+        let mut unmatched = Vec::new();
+        loop {
+            let mut pattern = std::ptr::null();
+            match unsafe {
+                carchive::archive_match_path_unmatched_inclusions_next(self.archive, &mut pattern)
+            } {
+                carchive::ARCHIVE_EOF => return Ok(unmatched),
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN if !pattern.is_null() => {
+                    let pattern = unsafe { CStr::from_ptr(pattern) };
+                    unmatched.push(pattern.to_string_lossy().into_owned());
+                }
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                _ => return Err(Error::from(self.archive)),
+            }
+        }
+    }
+
+    // true when the entry has to be left out. Matching inclusions are recorded
+    // for unmatched_inclusions.
+    pub(crate) fn excluded(&self, entry: *mut archive_entry) -> Result<bool> {
+        match unsafe { carchive::archive_match_excluded(self.archive, entry) } {
+            r if r < 0 => Err(Error::from(self.archive)),
+            r => Ok(r != 0),
+        }
+    }
+
+    // true when the entry matches an exclusion pattern. Unlike excluded, nothing
+    // is recorded, so it can be asked before excluded for the same entry.
+    pub(crate) fn pruned(&self, entry: *mut archive_entry) -> Result<bool> {
+        match unsafe { carchive::archive_match_path_excluded(self.exclusions, entry) } {
+            r if r < 0 => Err(Error::from(self.exclusions)),
+            r => Ok(r != 0),
+        }
+    }
+
+    fn check(&self, result: c_int) -> Result<()> {
+        check(self.archive, result)
+    }
+}
+
+fn check(archive: *mut archive, result: c_int) -> Result<()> {
+    match result {
+        carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+        _ => Err(Error::from(archive)),
+    }
+}
+
+impl Drop for Matcher {
+    fn drop(&mut self) {
+        unsafe {
+            carchive::archive_match_free(self.archive);
+            carchive::archive_match_free(self.exclusions);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::carchive;
+
+use std::{ffi::CString, ptr::null};
+
+use libc::{c_char, c_void};
+
+// user supplied passphrase source shared by the reader and the writer
+pub(crate) struct PassphraseCallback {
+    func: Box<dyn FnMut() -> Option<String>>,
+    // libarchive keeps the returned pointer until the callback is called again
+    current: Option<CString>,
+}
+
+impl PassphraseCallback {
+    pub(crate) fn new<F>(func: F) -> Self
+    where
+        F: FnMut() -> Option<String> + 'static,
+    {
+        PassphraseCallback {
+            func: Box::new(func),
+            current: None,
+        }
+    }
+}
+
+pub(crate) unsafe extern "C" fn archive_passphrase(
+    _: *mut carchive::archive,
+    client_data: *mut c_void,
+) -> *const c_char {
+    let callback = (client_data as *mut PassphraseCallback).as_mut().unwrap();
+    callback.current = (callback.func)().and_then(|p| CString::new(p).ok());
+
+    match &callback.current {
+        Some(passphrase) => passphrase.as_ptr(),
+        None => null(),
+    }
+}
@@ -1,5 +1,11 @@
 use crate::{
     carchive::{self, archive_entry, archive_entry_free, archive_read_free},
+    error::error_message,
+    extract::{check_entry, rebase_entry, DiskWriter, ExtractOptions},
+    format::{archive_filters, archive_format, Filter, FilterInfo, Format, FormatInfo},
+    index::{jumpable, zip_local_headers, ArchiveIndex},
+    matcher::Matcher,
+    passphrase::{archive_passphrase, PassphraseCallback},
     prelude::*,
     Metadata,
 };
@@ -9,25 +15,101 @@ use libc::{c_int, c_void};
 use crate::carchive::archive;
 use std::{
     ffi::CString,
-    io::{Error as IOError, ErrorKind, Read, Seek, SeekFrom},
+    io::{Cursor, Error as IOError, ErrorKind, Read, Seek, SeekFrom},
+    marker::PhantomData,
     mem::MaybeUninit,
+    path::Path,
 };
 
 const BUFFER_SIZE: usize = 16384;
 
-pub struct ArchiveReader<R: Read + Seek> {
+pub struct ArchiveReader<R: Read> {
     archive_reader: Option<*mut archive>,
     #[allow(dead_code)]
     fileref: Box<SourceReader<R>>,
     current_entry: Option<Metadata>,
+    current_encrypted: bool,
+    exhausted: bool,
+    options: ReaderOptions,
 }
 
-struct SourceReader<R: Read + Seek> {
+/// Configures an [`ArchiveReader`] before the source is opened.
+pub struct ArchiveReaderBuilder<R: Read> {
+    options: ReaderOptions,
+    source: PhantomData<R>,
+}
+
+// settings that have to be applied again every time the archive is reopened
+#[derive(Default)]
+struct ReaderOptions {
+    // None enables everything libarchive supports
+    formats: Option<Vec<Format>>,
+    filters: Option<Vec<Filter>>,
+    passphrases: Vec<CString>,
+    passphrase_callback: Option<Box<PassphraseCallback>>,
+    matcher: Option<Matcher>,
+}
+
+/// Walks the entries of an [`ArchiveReader`]. Each [`Entry`] borrows the walker, so it has
+/// to be dropped before advancing to the next one.
+pub struct Entries<'a, R: Read> {
+    reader: &'a mut ArchiveReader<R>,
+}
+
+/// A single archive entry. Reading from it yields exactly the data of this entry.
+pub struct Entry<'a, R: Read> {
+    reader: &'a mut ArchiveReader<R>,
+    meta: Metadata,
+}
+
+/// A chunk of entry data and where it goes in the file. Gaps between blocks are
+/// holes of a sparse file.
+pub struct DataBlock<'a> {
+    offset: i64,
+    data: &'a [u8],
+}
+
+impl DataBlock<'_> {
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+}
+
+struct SourceReader<R: Read> {
     obj: R,
+    // how libarchive gets the data of obj, buffer is what it is copied into
+    read: carchive::archive_read_callback,
     buffer: Box<[u8]>,
+    // offset of the source libarchive started reading at, its offsets count from there
+    base: u64,
+    // bytes handed to libarchive or skipped, what a stream is seeked from
+    position: u64,
+    // libarchive asked a stream to move back or to its end
+    seek_failed: bool,
 }
 
-unsafe extern "C" fn archivereader_read<R: Read + Seek>(
+impl<R: Read> SourceReader<R> {
+    fn new(
+        obj: R,
+        read: unsafe extern "C" fn(*mut archive, *mut c_void, *mut *const c_void) -> carchive::la_ssize_t,
+        buffer_size: usize,
+    ) -> Self {
+        SourceReader {
+            obj,
+            read: Some(read),
+            buffer: vec![0; buffer_size].into_boxed_slice(),
+            base: 0,
+            position: 0,
+            seek_failed: false,
+        }
+    }
+}
+
+unsafe extern "C" fn archivereader_read<R: Read>(
     archive: *mut carchive::archive,
     client_data: *mut c_void,
     buffer: *mut *const c_void,
@@ -37,7 +119,10 @@ unsafe extern "C" fn archivereader_read<R: Read + Seek>(
 
     // match pipe.reader.read(pipe.buffer) {
     match reader.obj.read(reader.buffer.as_mut()) {
-        Ok(size) => size as carchive::la_ssize_t,
+        Ok(size) => {
+            reader.position += size as u64;
+            size as carchive::la_ssize_t
+        }
         Err(e) => {
             let description = CString::new(e.to_string()).unwrap();
 
@@ -52,6 +137,52 @@ unsafe extern "C" fn archivereader_read<R: Read + Seek>(
     }
 }
 
+// data in memory is handed over from where the cursor is up to its end, uncopied
+unsafe extern "C" fn archivereader_read_slice(
+    _: *mut carchive::archive,
+    client_data: *mut c_void,
+    buffer: *mut *const c_void,
+) -> carchive::la_ssize_t {
+    let reader = (client_data as *mut SourceReader<Cursor<&[u8]>>)
+        .as_mut()
+        .unwrap();
+    let data = *reader.obj.get_ref();
+    let rest = &data[(reader.obj.position() as usize).min(data.len())..];
+    *buffer = rest.as_ptr() as *const c_void;
+
+    reader.obj.set_position(data.len() as u64);
+    rest.len() as carchive::la_ssize_t
+}
+
+// a stream only moves forward, by reading what is skipped. Anything else is
+// recorded, so the format that asked for it can be reported.
+unsafe extern "C" fn archivereader_stream_seek<R: Read>(
+    _: *mut carchive::archive,
+    client_data: *mut c_void,
+    offset: carchive::la_int64_t,
+    whence: c_int,
+) -> i64 {
+    let stream = (client_data as *mut SourceReader<R>).as_mut().unwrap();
+    let target = match whence {
+        0 => offset,
+        1 => stream.position as i64 + offset,
+        _ => -1,
+    };
+    if target < stream.position as i64 {
+        stream.seek_failed = true;
+        return -1;
+    }
+
+    let skip = target as u64 - stream.position;
+    match std::io::copy(&mut (&mut stream.obj).take(skip), &mut std::io::sink()) {
+        Ok(skipped) => {
+            stream.position += skipped;
+            stream.position as i64
+        }
+        Err(_) => -1,
+    }
+}
+
 unsafe extern "C" fn archivereader_seek<R: Read + Seek>(
     _: *mut carchive::archive,
     client_data: *mut c_void,
@@ -60,136 +191,571 @@ unsafe extern "C" fn archivereader_seek<R: Read + Seek>(
 ) -> i64 {
     let seeker = (client_data as *mut SourceReader<R>).as_mut().unwrap();
     let whence = match whence {
-        0 => SeekFrom::Start(offset as u64),
+        0 => SeekFrom::Start(seeker.base + offset as u64),
         1 => SeekFrom::Current(offset),
         2 => SeekFrom::End(offset),
         _ => return -1,
     };
 
     match seeker.obj.seek(whence) {
-        Ok(offset) => offset as i64,
+        Ok(offset) => offset as i64 - seeker.base as i64,
         Err(_) => -1,
     }
 }
 
+impl<R: Read> ArchiveReaderBuilder<R> {
+    /// Only accept these formats instead of every format libarchive knows about.
+    pub fn formats<I: IntoIterator<Item = Format>>(mut self, formats: I) -> Self {
+        self.options.formats = Some(formats.into_iter().collect());
+        self
+    }
+
+    /// Only accept these filters. Data compressed with anything else is handed to
+    /// the format detection as is and will fail with `Error::UnsupportedFormat`.
+    /// Uncompressed data is always accepted.
+    pub fn filters<I: IntoIterator<Item = Filter>>(mut self, filters: I) -> Self {
+        self.options.filters = Some(filters.into_iter().collect());
+        self
+    }
+
+    /// Adds a passphrase to try on encrypted entries. Can be called several times.
+    pub fn passphrase(mut self, passphrase: &str) -> Self {
+        self.options
+            .passphrases
+            .push(CString::new(passphrase.to_string()).unwrap());
+        self
+    }
+
+    /// Sets a function asked for passphrases once the ones given with `passphrase`
+    /// did not work. Returning `None` gives up.
+    pub fn passphrase_callback<F>(mut self, func: F) -> Self
+    where
+        F: FnMut() -> Option<String> + 'static,
+    {
+        self.options.passphrase_callback = Some(Box::new(PassphraseCallback::new(func)));
+        self
+    }
+
+    /// Leaves out the entries `matcher` excludes, when iterating, listing,
+    /// seeking or extracting.
+    pub fn matcher(mut self, matcher: Matcher) -> Self {
+        self.options.matcher = Some(matcher);
+        self
+    }
+
+    pub fn open(self, source: R) -> Result<ArchiveReader<R>>
+    where
+        R: Seek,
+    {
+        let source = SourceReader::new(source, archivereader_read::<R>, BUFFER_SIZE);
+        ArchiveReader::open(source, archivereader_seek::<R>, self.options)
+    }
+
+    pub fn open_streaming(self, source: R) -> Result<ArchiveReader<R>> {
+        let source = SourceReader::new(source, archivereader_read::<R>, BUFFER_SIZE);
+        ArchiveReader::open(source, archivereader_stream_seek::<R>, self.options)
+    }
+}
+
+impl<'a> ArchiveReaderBuilder<Cursor<&'a [u8]>> {
+    /// Reads the archive straight from `data`, without copying it. Seeking is
+    /// supported, so every format works.
+    pub fn open_bytes(self, data: &'a [u8]) -> Result<ArchiveReader<Cursor<&'a [u8]>>> {
+        let source = SourceReader::new(Cursor::new(data), archivereader_read_slice, 0);
+        ArchiveReader::open(source, archivereader_seek::<Cursor<&[u8]>>, self.options)
+    }
+}
+
+impl<'a> ArchiveReader<Cursor<&'a [u8]>> {
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self> {
+        ArchiveReader::builder().open_bytes(data)
+    }
+}
+
 impl<R: Read + Seek> ArchiveReader<R> {
     pub fn new(source: R) -> Result<Self> {
-        let buffer = [0; BUFFER_SIZE];
-        let mut fref = Box::new(SourceReader {
-            obj: source,
-            buffer: Box::new(buffer),
-        });
+        ArchiveReader::builder().open(source)
+    }
+
+    pub fn reader_seek_obj(&mut self, filename: &str) -> Result<()> {
+        self.reopen_at(0, false)?;
+
+        while let Some(meta) = self.next_header()? {
+            if meta.filepath() == filename {
+                self.current_entry = Some(meta);
+                return Ok(());
+            }
+        }
+        Err(not_found(filename))
+    }
+
+    /// Reads every header of the archive, from the start, and records where each
+    /// entry is. The reader is left at the end of the archive.
+    pub fn build_index(&mut self) -> Result<ArchiveIndex> {
+        Ok(self.scan_headers()?.0)
+    }
+
+    // build_index, also handing back the metadata of every entry read on the way
+    pub(crate) fn scan_headers(&mut self) -> Result<(ArchiveIndex, Vec<Metadata>)> {
+        self.reopen_at(0, false)?;
+
+        let mut entries = Vec::new();
+        while let Some(entry) = self.next_header_at()? {
+            entries.push(entry);
+        }
+
+        // an archive without entries never reports its format
+        let format = self.format().ok().and_then(|f| f.format());
+        let uncompressed = self
+            .filters()?
+            .iter()
+            .all(|f| f.filter() == Some(Filter::None));
+
+        // zip entries are jumped to at their local header, found in the central
+        // directory. Names libarchive reads differently keep the whole index scanning.
+        let local_headers = match format {
+            Some(Format::Zip) if uncompressed => {
+                zip_local_headers(&mut self.fileref.obj)?.filter(|headers| {
+                    entries
+                        .iter()
+                        .all(|(_, meta)| headers.contains_key(meta.filepath()))
+                })
+            }
+            _ => None,
+        };
+        let direct = local_headers.is_some() || uncompressed && format.is_some_and(jumpable);
+
+        let mut index = ArchiveIndex::new(format, direct);
+        for (position, meta) in &entries {
+            let position = match &local_headers {
+                Some(headers) => headers[meta.filepath()],
+                None => *position,
+            };
+            index.push(meta.filepath().to_owned(), position);
+        }
+        Ok((index, entries.into_iter().map(|(_, meta)| meta).collect()))
+    }
+
+    /// Same as `reader_seek_obj`, using the positions of `index` to get to the entry
+    /// without reading the ones before it when the format allows it. A path stored
+    /// twice is read from the copy the index points to.
+    pub fn seek_index(&mut self, index: &ArchiveIndex, filename: &str) -> Result<()> {
+        let position = index
+            .position(filename)
+            .ok_or_else(|| not_found(filename))?;
+        if !index.is_direct() {
+            return self.seek_scan(filename, position);
+        }
+
+        self.reopen_at(position as u64, index.format() == Some(Format::Zip))?;
+        match self.next_header()? {
+            Some(meta) if meta.filepath() == filename => {
+                self.current_entry = Some(meta);
+                Ok(())
+            }
+            _ => Err(moved(filename, position)),
+        }
+    }
+
+    // reads the headers from the start. A path stored twice is told apart by the
+    // position of the copy that was indexed.
+    fn seek_scan(&mut self, filename: &str, position: i64) -> Result<()> {
+        self.reopen_at(0, false)?;
+
+        while let Some((at, meta)) = self.next_header_at()? {
+            if at == position && meta.filepath() == filename {
+                self.current_entry = Some(meta);
+                return Ok(());
+            }
+        }
+        Err(moved(filename, position))
+    }
+
+    // starts reading again with a new archive, offset bytes into the source. A
+    // zip entry is read on its own from its local header.
+    fn reopen_at(&mut self, offset: u64, zip_entry: bool) -> Result<()> {
+        let archive = self.get_archive()?;
+        self.archive_reader = None;
+        self.current_entry = None;
+        self.exhausted = false;
+
+        unsafe {
+            match archive_read_free(archive) {
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                _ => return Err(archive.into()),
+            };
+
+            self.fileref.obj.seek(SeekFrom::Start(offset))?;
+            self.fileref.base = offset;
+            let archive = ArchiveReader::start(
+                &mut self.fileref,
+                Some(archivereader_seek::<R>),
+                &mut self.options,
+                zip_entry,
+            )?;
+            self.archive_reader = Some(archive);
+        }
+        Ok(())
+    }
+}
+
+// the archive changed since the index was built
+fn moved(filename: &str, position: i64) -> Error {
+    Error::InvalidIndex(format!("no entry '{}' at offset {}", filename, position))
+}
+
+fn not_found(filename: &str) -> Error {
+    IOError::new(
+        ErrorKind::NotFound,
+        format!("path {} doesn't exist inside archive", filename),
+    )
+    .into()
+}
+
+impl<R: Read> ArchiveReader<R> {
+    /// Opens a source that cannot seek, like a pipe or a socket. Formats that need to
+    /// go back in the file, like 7z, fail with `Error::SeekRequired`, at the latest
+    /// when the data of an entry is read. Zip archives behind a prefix, like
+    /// self-extracting ones, are only found through their central directory at the
+    /// end and fail to open with `Error::UnsupportedFormat`.
+    pub fn new_streaming(source: R) -> Result<Self> {
+        ArchiveReader::builder().open_streaming(source)
+    }
+
+    pub fn builder() -> ArchiveReaderBuilder<R> {
+        ArchiveReaderBuilder {
+            options: ReaderOptions::default(),
+            source: PhantomData,
+        }
+    }
+
+    fn open(
+        source: SourceReader<R>,
+        seeker: unsafe extern "C" fn(*mut archive, *mut c_void, i64, c_int) -> i64,
+        mut options: ReaderOptions,
+    ) -> Result<Self> {
+        let mut fref = Box::new(source);
 
         unsafe {
             Ok(ArchiveReader {
-                archive_reader: Some(ArchiveReader::start(&mut fref)?),
+                archive_reader: Some(ArchiveReader::start(
+                    &mut fref,
+                    Some(seeker),
+                    &mut options,
+                    false,
+                )?),
                 fileref: fref,
                 current_entry: Option::None,
+                current_encrypted: false,
+                exhausted: false,
+                options,
             })
         }
     }
 
-    unsafe fn start(fref: &mut Box<SourceReader<R>>) -> Result<*mut archive> {
+    unsafe fn start(
+        fref: &mut Box<SourceReader<R>>,
+        seeker: carchive::archive_seek_callback,
+        options: &mut ReaderOptions,
+        zip_entry: bool,
+    ) -> Result<*mut archive> {
+        let archive_reader = ArchiveReader::<R>::configure(seeker, options, zip_entry)?;
+
+        let r = carchive::archive_read_open(
+            archive_reader,
+            std::ptr::addr_of_mut!(**fref) as *mut c_void,
+            None,
+            fref.read,
+            None,
+        );
+        ArchiveReader::<R>::check_open(archive_reader, r)?;
+        // bidders probe the end of the file, only later seeks need a seekable source
+        fref.seek_failed = false;
+
+        Ok(archive_reader)
+    }
+
+    // a new archive with the formats, filters and passphrases of options, ready to be opened
+    unsafe fn configure(
+        seeker: carchive::archive_seek_callback,
+        options: &mut ReaderOptions,
+        zip_entry: bool,
+    ) -> Result<*mut archive> {
         let archive_reader = carchive::archive_read_new();
 
         if archive_reader.is_null() {
             return Err(Error::NullArchive);
         }
 
-        match carchive::archive_read_support_filter_all(archive_reader) {
-            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
-            _ => return Err(Error::from(archive_reader)),
+        match &options.filters {
+            // zip is not compressed as a whole
+            _ if zip_entry => (),
+            Some(filters) => {
+                for filter in filters {
+                    filter.read_support(archive_reader)?;
+                }
+            }
+            None => match carchive::archive_read_support_filter_all(archive_reader) {
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                _ => return Err(Error::from(archive_reader)),
+            },
         };
 
-        match carchive::archive_read_support_format_all(archive_reader) {
-            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
-            _ => return Err(Error::from(archive_reader)),
+        match &options.formats {
+            // the central directory that zip is otherwise read from is left behind
+            _ if zip_entry => {
+                match carchive::archive_read_support_format_zip_streamable(archive_reader) {
+                    carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                    _ => return Err(Error::from(archive_reader)),
+                }
+            }
+            Some(formats) => {
+                for format in formats {
+                    format.read_support(archive_reader)?;
+                }
+            }
+            None => match carchive::archive_read_support_format_all(archive_reader) {
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                _ => return Err(Error::from(archive_reader)),
+            },
         };
 
-        match carchive::archive_read_set_seek_callback(
-            archive_reader,
-            Some(archivereader_seek::<R>),
-        ) {
-            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
-            _ => return Err(Error::from(archive_reader)),
+        for passphrase in &options.passphrases {
+            match carchive::archive_read_add_passphrase(archive_reader, passphrase.as_ptr()) {
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                _ => return Err(Error::from(archive_reader)),
+            }
         }
 
-        match carchive::archive_read_open(
-            archive_reader,
-            std::ptr::addr_of_mut!(**fref) as *mut c_void,
-            None,
-            Some(archivereader_read::<R>),
-            None,
-        ) {
-            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
-            _ => return Err(Error::from(archive_reader)),
-        };
+        if let Some(callback) = options.passphrase_callback.as_mut() {
+            match carchive::archive_read_set_passphrase_callback(
+                archive_reader,
+                std::ptr::addr_of_mut!(**callback) as *mut c_void,
+                Some(archive_passphrase),
+            ) {
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                _ => return Err(Error::from(archive_reader)),
+            }
+        }
+
+        // without a seek callback libarchive falls back to skipping forward
+        if seeker.is_some() {
+            match carchive::archive_read_set_seek_callback(archive_reader, seeker) {
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                _ => return Err(Error::from(archive_reader)),
+            }
+        }
 
         Ok(archive_reader)
     }
 
-    pub fn list_files(self) -> Result<Vec<Metadata>> {
-        let archive = self.get_archive()?;
+    unsafe fn check_open(archive_reader: *mut archive, result: c_int) -> Result<()> {
+        match result {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+            // no enabled format recognized the data
+            _ if carchive::archive_errno(archive_reader) == libc::EILSEQ => {
+                Err(Error::UnsupportedFormat(error_message(archive_reader)))
+            }
+            _ => Err(Error::from(archive_reader)),
+        }
+    }
 
+    pub fn list_files(&mut self) -> Result<Vec<Metadata>> {
         let mut outlist = Vec::<Metadata>::new();
 
+        while let Some(meta) = self.next_header()? {
+            outlist.push(meta);
+        }
+
+        Ok(outlist)
+    }
+
+    /// Format of the archive. Only available once the first header has been read,
+    /// before that it fails with `Error::UnknownFormat`.
+    pub fn format(&self) -> Result<FormatInfo> {
+        archive_format(self.get_archive()?)
+    }
+
+    /// Filters applied on top of the archive data, outermost decoding step first. The
+    /// last one is always the `none` filter reading the raw source.
+    pub fn filters(&self) -> Result<Vec<FilterInfo>> {
+        Ok(archive_filters(self.get_archive()?))
+    }
+
+    pub fn entries(&mut self) -> Entries<'_, R> {
+        Entries { reader: self }
+    }
+
+    // reads the next header, once the archive reached its end or failed fatally
+    // it keeps returning None
+    fn next_header(&mut self) -> Result<Option<Metadata>> {
+        Ok(self.next_header_at()?.map(|(_, meta)| meta))
+    }
+
+    // next header along with its position, entries left out by the matcher are
+    // skipped
+    fn next_header_at(&mut self) -> Result<Option<(i64, Metadata)>> {
+        if self.exhausted {
+            return Ok(None);
+        }
+
+        let archive = self.get_archive()?;
         loop {
+            let mut entry = MaybeUninit::<*mut archive_entry>::uninit();
+
             unsafe {
-                let mut entry = std::mem::MaybeUninit::<*mut archive_entry>::uninit();
                 match carchive::archive_read_next_header(archive, entry.as_mut_ptr()) {
-                    carchive::ARCHIVE_EOF => break,
+                    carchive::ARCHIVE_EOF => {
+                        self.exhausted = true;
+                        return Ok(None);
+                    }
                     carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => {
                         let entry = entry.assume_init();
-                        outlist.push(entry.into());
+                        if self.excluded(entry)? {
+                            continue;
+                        }
+                        // only set once the data of the previous entry is skipped
+                        let position = carchive::archive_read_header_position(archive);
+                        let meta: Metadata = entry.into();
+                        self.current_encrypted = meta.is_encrypted();
+                        return Ok(Some((position, meta)));
                     }
-                    _ => return Err(Error::from(archive)),
-                };
+                    carchive::ARCHIVE_FATAL => {
+                        self.exhausted = true;
+                        return Err(self.header_error(archive));
+                    }
+                    _ => return Err(self.header_error(archive)),
+                }
             }
         }
+    }
 
-        Ok(outlist)
+    fn excluded(&self, entry: *mut archive_entry) -> Result<bool> {
+        match &self.options.matcher {
+            Some(matcher) => matcher.excluded(entry),
+            None => Ok(false),
+        }
     }
 
-    pub fn reader_whole_archive(self) -> Result<()> {
-        Ok(())
+    /// Inclusion patterns of the matcher no entry read so far matched.
+    pub fn unmatched_inclusions(&mut self) -> Result<Vec<String>> {
+        match self.options.matcher.as_mut() {
+            Some(matcher) => matcher.unmatched_inclusions(),
+            None => Ok(Vec::new()),
+        }
     }
 
-    pub fn reader_seek_obj(&mut self, filename: &str) -> Result<()> {
+    fn header_error(&self, archive: *mut archive) -> Error {
+        if let Some(e) = self.seek_error(archive) {
+            return e;
+        }
+
+        // formats encrypting their metadata (7z) need the passphrase to read headers
+        let encrypted_headers = unsafe {
+            carchive::archive_read_has_encrypted_entries(archive) > 0
+                && carchive::archive_read_format_capabilities(archive)
+                    & carchive::ARCHIVE_READ_FORMAT_CAPS_ENCRYPT_METADATA
+                    != 0
+        };
+        if encrypted_headers {
+            return Error::Passphrase(error_message(archive));
+        }
+
+        Error::from(archive)
+    }
+
+    // a stream was asked to go back, libarchive only reports the failed seek so
+    // the format that needed it is named instead
+    fn seek_error(&self, archive: *mut archive) -> Option<Error> {
+        if !self.fileref.seek_failed {
+            return None;
+        }
+        let name = archive_format(archive)
+            .map(|f| f.name().to_owned())
+            .unwrap_or_default();
+        Some(Error::SeekRequired(name))
+    }
+
+    // a missing or wrong passphrase shows up as a generic data error, only its
+    // message tells it apart from damaged data
+    fn data_error(&self, archive: *mut archive) -> Error {
+        if let Some(e) = self.seek_error(archive) {
+            return e;
+        }
+
+        let message = error_message(archive);
+        if self.current_encrypted && message.to_lowercase().contains("passphrase") {
+            return Error::Passphrase(message);
+        }
+
+        Error::from(archive)
+    }
+
+    /// Next block of data of the current entry, None once it is over. Unlike `read`
+    /// holes are skipped, not filled with zeros. Both can't be mixed on one entry.
+    pub fn read_block(&mut self) -> Result<Option<DataBlock<'_>>> {
         let archive = self.get_archive()?;
+        let mut buffer = std::ptr::null();
+        let mut size = 0;
+        let mut offset = 0;
+
+        match unsafe {
+            carchive::archive_read_data_block(archive, &mut buffer, &mut size, &mut offset)
+        } {
+            carchive::ARCHIVE_EOF => Ok(None),
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => {
+                let data = if size == 0 {
+                    &[]
+                } else {
+                    unsafe { std::slice::from_raw_parts(buffer as *const u8, size) }
+                };
+                Ok(Some(DataBlock { offset, data }))
+            }
+            _ => Err(self.data_error(archive)),
+        }
+    }
 
-        unsafe {
-            self.fileref.obj.seek(SeekFrom::Start(0))?;
-            match archive_read_free(archive) {
-                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
-                _ => return Err(archive.into()),
-            };
+    /// Extracts the archive into the current directory with the default options.
+    #[deprecated(note = "use extract_to, which takes the destination and options")]
+    pub fn reader_whole_archive(mut self) -> Result<()> {
+        self.extract_to(".", &ExtractOptions::default())
+    }
 
-            let archive = ArchiveReader::start(&mut self.fileref)?;
-            self.archive_reader = Some(archive);
+    /// Extracts every remaining entry of the archive below `dest`, creating it if needed.
+    /// Holes of sparse entries are recreated, not written as zeros.
+    /// Entries left out by the matcher are skipped.
+    pub fn extract_to<P: AsRef<Path>>(&mut self, dest: P, options: &ExtractOptions) -> Result<()> {
+        let archive = self.get_archive()?;
+        let disk = DiskWriter::new(options)?;
 
-            loop {
-                let mut entry = std::mem::MaybeUninit::<*mut archive_entry>::uninit();
-                let hdr_result = carchive::archive_read_next_header(archive, entry.as_mut_ptr());
-                let entry = entry.assume_init();
-                match hdr_result {
-                    carchive::ARCHIVE_EOF => {
-                        return Err(IOError::new(
-                            ErrorKind::NotFound,
-                            format!("path {} doesn't exist inside archive", filename),
-                        )
-                        .into());
-                    }
+        std::fs::create_dir_all(dest.as_ref())?;
+        let dest = std::fs::canonicalize(dest)?;
+
+        loop {
+            unsafe {
+                let mut entry = MaybeUninit::<*mut archive_entry>::uninit();
+                match carchive::archive_read_next_header(archive, entry.as_mut_ptr()) {
+                    carchive::ARCHIVE_EOF => break,
                     carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
-                    _ => return Err(archive.into()),
+                    _ => return Err(self.header_error(archive)),
                 };
 
-                let meta: Metadata = entry.into();
-                if meta.filepath() == filename {
-                    self.current_entry = Some(meta);
-                    return Ok(());
+                let entry = entry.assume_init();
+                if self.excluded(entry)? {
+                    continue;
                 }
+                check_entry(entry, &dest, options)?;
+                rebase_entry(entry, &dest);
+                self.current_encrypted = carchive::archive_entry_is_encrypted(entry) != 0;
+
+                match carchive::archive_read_extract2(archive, entry, disk.as_ptr()) {
+                    carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+                    _ => return Err(self.data_error(archive)),
+                };
             }
         }
+
+        disk.close()
     }
 
     fn get_archive(&self) -> Result<*mut archive> {
@@ -210,7 +776,7 @@ impl<R: Read + Seek> ArchiveReader<R> {
     }
 }
 
-impl<R: Read + Seek> Read for ArchiveReader<R> {
+impl<R: Read> Read for ArchiveReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let archive = self.get_archive()?;
         let read_size = unsafe {
@@ -218,37 +784,50 @@ impl<R: Read + Seek> Read for ArchiveReader<R> {
         };
         match read_size {
             n if n >= 0 || n as usize <= buf.len() => Ok(n as usize),
-            _ => Err(Error::from(archive).into()),
+            _ => Err(self.data_error(archive).into()),
         }
     }
 }
 
-impl<R: Read + Seek> Iterator for ArchiveReader<R> {
-    type Item = Metadata;
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = Result<Metadata>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let archive;
-        let mut entry = MaybeUninit::<*mut archive_entry>::uninit();
+        self.next_header().transpose()
+    }
+}
 
-        if let Ok(res) = self.get_archive() {
-            archive = res;
-        } else {
-            return Option::None;
+impl<R: Read> Entries<'_, R> {
+    pub fn next_entry(&mut self) -> Option<Result<Entry<'_, R>>> {
+        match self.reader.next_header() {
+            Ok(Some(meta)) => Some(Ok(Entry {
+                reader: self.reader,
+                meta,
+            })),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
         }
+    }
+}
 
-        unsafe {
-            match carchive::archive_read_next_header(archive, entry.as_mut_ptr()) {
-                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => {
-                    let entry = entry.assume_init();
-                    Some(entry.into())
-                }
-                _ => Option::None,
-            }
-        }
+impl<R: Read> Entry<'_, R> {
+    pub fn metadata(&self) -> &Metadata {
+        &self.meta
+    }
+
+    /// See [`ArchiveReader::read_block`].
+    pub fn read_block(&mut self) -> Result<Option<DataBlock<'_>>> {
+        self.reader.read_block()
+    }
+}
+
+impl<R: Read> Read for Entry<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
     }
 }
 
-impl<R: Read + Seek> Drop for ArchiveReader<R> {
+impl<R: Read> Drop for ArchiveReader<R> {
     fn drop(&mut self) {
         drop(self.free());
     }
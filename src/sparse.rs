@@ -0,0 +1,64 @@
+use crate::carchive::{self, archive_entry};
+
+use std::{fs::File, io, os::unix::io::AsRawFd};
+
+// (offset, length) of the parts of the file holding data, found with
+// SEEK_DATA/SEEK_HOLE. Filesystems without hole support report a single region.
+// The probing moves the shared file offset, it is put back where it was.
+pub(crate) fn data_regions(file: &File, size: i64) -> io::Result<Vec<(i64, i64)>> {
+    let fd = file.as_raw_fd();
+    let position = unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) };
+    if position < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let regions = probe_regions(fd, size);
+    if unsafe { libc::lseek(fd, position, libc::SEEK_SET) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    regions
+}
+
+fn probe_regions(fd: libc::c_int, size: i64) -> io::Result<Vec<(i64, i64)>> {
+    let mut regions = Vec::new();
+    let mut offset = 0;
+
+    while offset < size {
+        let data = unsafe { libc::lseek(fd, offset, libc::SEEK_DATA) };
+        if data < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                // only a hole is left up to the end of the file
+                Some(libc::ENXIO) => break,
+                Some(libc::EINVAL) | Some(libc::ENOTSUP) => return Ok(vec![(0, size)]),
+                _ => return Err(err),
+            }
+        }
+        if data >= size {
+            break;
+        }
+
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let end = hole.min(size);
+        if end <= data {
+            break;
+        }
+        regions.push((data, end - data));
+        offset = end;
+    }
+
+    Ok(regions)
+}
+
+// the size of the entry must be set first, regions past it are dropped
+pub(crate) fn set_sparse_map(entry: *mut archive_entry, regions: &[(i64, i64)]) {
+    unsafe {
+        carchive::archive_entry_sparse_clear(entry);
+        for (offset, length) in regions {
+            carchive::archive_entry_sparse_add_entry(entry, *offset, *length);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    format::{Filter, FilterInfo, Format},
+    prelude::*,
+    reader::ArchiveReader,
+    writer::ArchiveWriter,
+};
+
+use std::io::{Read, Write};
+
+/// Compresses everything written to it with a single filter, no archive container
+/// around it, like `gzip` or `xz` do. `finish` has to be called to get the end of
+/// the stream written out.
+pub struct Compressor<W: Write> {
+    writer: ArchiveWriter<W>,
+}
+
+/// Decompresses a stream, the filters are detected from the data. Data that is
+/// not compressed at all is passed through as is.
+pub struct Decompressor<R: Read> {
+    reader: ArchiveReader<R>,
+    // an empty source has no entry to read from
+    empty: bool,
+}
+
+impl<W: Write> Compressor<W> {
+    pub fn new(dest: W, filter: Filter) -> Result<Self> {
+        Compressor::start(dest, filter, None)
+    }
+
+    /// Same as `new` with a filter specific compression level, e.g. 1 to 9 for gzip.
+    pub fn with_level(dest: W, filter: Filter, level: u32) -> Result<Self> {
+        Compressor::start(dest, filter, Some(level))
+    }
+
+    fn start(dest: W, filter: Filter, level: Option<u32>) -> Result<Self> {
+        let mut writer = ArchiveWriter::new(dest)?;
+        writer.set_output_format(Format::Raw)?;
+        writer.set_output_filter(filter)?;
+        if let Some(level) = level {
+            writer.add_filter_option("compression-level", &level.to_string())?;
+        }
+        writer.open()?;
+        writer.start_raw_entry()?;
+        Ok(Compressor { writer })
+    }
+
+    /// Flushes the end of the compressed stream and hands the sink back.
+    pub fn finish(self) -> Result<W> {
+        Ok(self.writer.finish()?.0)
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(self.writer.write_bytes(buf)?)
+    }
+
+    // the filters keep their own buffers, only finish empties them
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<R: Read> Decompressor<R> {
+    pub fn new(source: R) -> Result<Self> {
+        let mut reader = ArchiveReader::builder()
+            .formats([Format::Raw, Format::Empty])
+            .open_streaming(source)?;
+        let empty = reader.next().transpose()?.is_none();
+        Ok(Decompressor { reader, empty })
+    }
+
+    /// Filters found in the stream, outermost first, ending with `none`.
+    pub fn filters(&self) -> Result<Vec<FilterInfo>> {
+        self.reader.filters()
+    }
+}
+
+impl<R: Read> Read for Decompressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.empty {
+            return Ok(0);
+        }
+        self.reader.read(buf)
+    }
+}
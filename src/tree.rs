@@ -0,0 +1,202 @@
+use crate::{
+    carchive::{self, archive, archive_entry, OwnedEntry},
+    prelude::*,
+};
+
+use std::{
+    ffi::{CStr, CString},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+use libc::c_int;
+
+/// How symlinks found while walking a directory are stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Never follow symlinks, store them as links (the `tar` default).
+    #[default]
+    Physical,
+    /// Follow every symlink and store what it points to.
+    Logical,
+    /// Follow a symlink given as the root directory, store every other one as a link.
+    Hybrid,
+}
+
+/// Controls how `ArchiveWriter::add_tree` walks a directory.
+#[derive(Clone, Debug)]
+pub struct TreeOptions {
+    symlinks: SymlinkPolicy,
+    xattrs: bool,
+    acls: bool,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        TreeOptions {
+            symlinks: SymlinkPolicy::default(),
+            xattrs: true,
+            acls: true,
+        }
+    }
+}
+
+impl TreeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select how symlinks are handled.
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Store the extended attributes of every file.
+    pub fn xattrs(mut self, enable: bool) -> Self {
+        self.xattrs = enable;
+        self
+    }
+
+    /// Store the ACLs of every file.
+    pub fn acls(mut self, enable: bool) -> Self {
+        self.acls = enable;
+        self
+    }
+
+    fn behavior(&self) -> c_int {
+        // holes are looked up by the writer when the data is copied
+        let mut flags = carchive::ARCHIVE_READDISK_NO_SPARSE;
+        if !self.xattrs {
+            flags |= carchive::ARCHIVE_READDISK_NO_XATTR;
+        }
+        if !self.acls {
+            flags |= carchive::ARCHIVE_READDISK_NO_ACL;
+        }
+        flags
+    }
+}
+
+// owned archive_read_disk handle, either walking a single directory tree or
+// describing files one by one
+pub(crate) struct DiskReader {
+    archive: *mut archive,
+}
+
+impl DiskReader {
+    pub(crate) fn new(options: &TreeOptions) -> Result<Self> {
+        let archive = unsafe { carchive::archive_read_disk_new() };
+        if archive.is_null() {
+            return Err(Error::NullArchive);
+        }
+
+        let reader = DiskReader { archive };
+
+        match unsafe {
+            match options.symlinks {
+                SymlinkPolicy::Physical => {
+                    carchive::archive_read_disk_set_symlink_physical(archive)
+                }
+                SymlinkPolicy::Logical => carchive::archive_read_disk_set_symlink_logical(archive),
+                SymlinkPolicy::Hybrid => carchive::archive_read_disk_set_symlink_hybrid(archive),
+            }
+        } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+            _ => return Err(Error::from(archive)),
+        }
+
+        match unsafe { carchive::archive_read_disk_set_behavior(archive, options.behavior()) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+            _ => return Err(Error::from(archive)),
+        }
+
+        match unsafe { carchive::archive_read_disk_set_standard_lookup(archive) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+            _ => return Err(Error::from(archive)),
+        }
+
+        Ok(reader)
+    }
+
+    pub(crate) fn open(&mut self, root: &Path) -> Result<()> {
+        let path = CString::new(root.as_os_str().as_bytes()).unwrap();
+        match unsafe { carchive::archive_read_disk_open(self.archive, path.as_ptr()) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+            _ => Err(Error::from(self.archive)),
+        }
+    }
+
+    // full description of a single file, symlinks are not followed. The data
+    // is read later from the sourcepath.
+    pub(crate) fn entry_from_file(&mut self, path: &Path) -> Result<OwnedEntry> {
+        let entry = OwnedEntry::new();
+        let p = CString::new(path.as_os_str().as_bytes()).unwrap();
+        unsafe {
+            carchive::archive_entry_copy_pathname(entry.as_ptr(), p.as_ptr());
+            carchive::archive_entry_copy_sourcepath(entry.as_ptr(), p.as_ptr());
+            match carchive::archive_read_disk_entry_from_file(
+                self.archive,
+                entry.as_ptr(),
+                -1,
+                std::ptr::null(),
+            ) {
+                carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(entry),
+                _ => Err(Error::from(self.archive)),
+            }
+        }
+    }
+
+    // next object on disk, None once the walk is over. The content of a
+    // directory is only walked when descend is called before the next entry.
+    pub(crate) fn next_entry(&mut self) -> Result<Option<OwnedEntry>> {
+        let entry = OwnedEntry::new();
+        match unsafe { carchive::archive_read_next_header2(self.archive, entry.as_ptr()) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(Some(entry)),
+            carchive::ARCHIVE_EOF => Ok(None),
+            _ => Err(Error::from(self.archive)),
+        }
+    }
+
+    // walks the content of the directory last returned by next_entry right after
+    // it, does nothing for anything else
+    pub(crate) fn descend(&mut self) -> Result<()> {
+        match unsafe { carchive::archive_read_disk_descend(self.archive) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+            _ => Err(Error::from(self.archive)),
+        }
+    }
+}
+
+impl Drop for DiskReader {
+    fn drop(&mut self) {
+        unsafe { carchive::archive_read_free(self.archive) };
+    }
+}
+
+// moves an entry found below root to the same place below prefix. The root
+// itself maps to prefix, or to nothing when the prefix is empty.
+pub(crate) fn rebase_pathname(entry: *mut archive_entry, root: &Path, prefix: &str) -> bool {
+    let pathname = unsafe { carchive::archive_entry_pathname(entry) };
+    if pathname.is_null() {
+        return false;
+    }
+    let name = unsafe { CStr::from_ptr(pathname) }.to_bytes();
+    let relative = name
+        .strip_prefix(root.as_os_str().as_bytes())
+        .unwrap_or(name);
+    let relative = relative.strip_prefix(b"/").unwrap_or(relative);
+    let prefix = prefix.trim_end_matches('/');
+
+    let mut out = prefix.as_bytes().to_vec();
+    if !out.is_empty() && !relative.is_empty() {
+        out.push(b'/');
+    }
+    out.extend_from_slice(relative);
+    if out.is_empty() {
+        return false;
+    }
+
+    let p = CString::new(out).unwrap();
+    unsafe { carchive::archive_entry_copy_pathname(entry, p.as_ptr()) };
+    true
+}
@@ -1,36 +1,106 @@
-use crate::{prelude::*, Metadata};
+use crate::{
+    hardlink::LinkResolver,
+    matcher::Matcher,
+    passphrase::{archive_passphrase, PassphraseCallback},
+    prelude::*,
+    sparse::{data_regions, set_sparse_map},
+    tree::{rebase_pathname, DiskReader, TreeOptions},
+    Metadata, AE_IFBLK, AE_IFCHR, AE_IFDIR, AE_IFIFO, AE_IFLNK, AE_IFREG,
+};
 
 use std::{
-    ffi::CString,
+    ffi::{CStr, CString, OsStr},
     fs::File,
-    io::{Read, Write},
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    path::Path,
     ptr::null_mut,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use libc::c_void;
 
 use crate::{
     carchive::{
-        self, archive, archive_entry_free, archive_entry_new, archive_entry_set_atime,
-        archive_entry_set_ctime, archive_entry_set_gid, archive_entry_set_mode,
-        archive_entry_set_mtime, archive_entry_set_pathname, archive_entry_set_perm,
-        archive_entry_set_size, archive_entry_set_uid, archive_write_data, archive_write_free,
-        archive_write_header,
+        self, archive, archive_entry_set_atime, archive_entry_set_ctime,
+        archive_entry_set_filetype, archive_entry_set_gid, archive_entry_set_mtime,
+        archive_entry_set_pathname, archive_entry_set_perm, archive_entry_set_size,
+        archive_entry_set_uid, archive_write_data, archive_write_free, archive_write_header,
+        OwnedEntry,
     },
-    ARCHIVE_FILTER_BZIP2, ARCHIVE_FILTER_GZIP, ARCHIVE_FILTER_LRZIP, ARCHIVE_FILTER_LZ4,
-    ARCHIVE_FILTER_LZIP, ARCHIVE_FILTER_LZMA, ARCHIVE_FILTER_LZOP, ARCHIVE_FILTER_NONE,
-    ARCHIVE_FILTER_XZ, ARCHIVE_FILTER_ZSTD, ARCHIVE_FORMAT_7ZIP, ARCHIVE_FORMAT_TAR,
-    ARCHIVE_FORMAT_XAR, ARCHIVE_FORMAT_ZIP,
+    format::{Filter, Format},
 };
-use std::os::raw::c_int;
 
 const BUFFER_SIZE: usize = 16384;
+const ZEROS_SIZE: usize = 1 << 20;
 
 pub struct ArchiveWriter<W: Write> {
     archive_writer: *mut archive,
-    fileref: Box<FileWriter<W>>,
-    file_format: c_int,
-    file_filter: c_int,
+    // only None once finish() handed the sink back
+    fileref: Option<Box<FileWriter<W>>>,
+    file_format: Option<Format>,
+    file_filter: Option<Filter>,
+    passphrase_callback: Option<Box<PassphraseCallback>>,
+    links: Option<LinkResolver>,
+    matcher: Option<Matcher>,
+    // an entry dropped before all its data was written, reported by the next call
+    short_entry: Option<Error>,
+}
+
+/// Encryption methods supported when writing zip archives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZipEncryption {
+    /// Traditional PKWARE encryption. Weak, only for compatibility with old tools.
+    ZipCrypt,
+    Aes128,
+    Aes256,
+}
+
+impl ZipEncryption {
+    fn option_value(&self) -> &'static str {
+        match self {
+            ZipEncryption::ZipCrypt => "zipcrypt",
+            ZipEncryption::Aes128 => "aes128",
+            ZipEncryption::Aes256 => "aes256",
+        }
+    }
+}
+
+/// Totals reported by `ArchiveWriter::finish`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteStats {
+    entries: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+impl WriteStats {
+    /// Number of entries written.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Bytes produced by the archive format, before any filter.
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in
+    }
+
+    /// Bytes handed to the sink, after every filter.
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out
+    }
+}
+
+/// Data of an entry started with `ArchiveWriter::start_entry`. Exactly the declared
+/// size has to be written before it is finished or dropped. An entry dropped short
+/// is padded with zeros and the next entry added or `finish` fail with
+/// `Error::SizeMismatch`.
+pub struct EntryWriter<'a, W: Write> {
+    writer: &'a mut ArchiveWriter<W>,
+    archivepath: String,
+    size: i64,
+    written: i64,
+    finished: bool,
 }
 
 struct FileWriter<W: Write> {
@@ -80,30 +150,50 @@ impl<W: Write> ArchiveWriter<W> {
 
             Ok(ArchiveWriter {
                 archive_writer,
-                fileref: fref,
-                file_format: -1,
-                file_filter: -1,
+                fileref: Some(fref),
+                file_format: None,
+                file_filter: None,
+                passphrase_callback: None,
+                links: None,
+                matcher: None,
+                short_entry: None,
             })
         }
     }
 
     // Raw Archive API
-    pub fn set_output_format(&mut self, format: c_int) -> Result<()> {
-        match unsafe { carchive::archive_write_set_format(self.archive_writer, format) } {
+    pub fn set_output_format(&mut self, format: Format) -> Result<()> {
+        format.write_support()?;
+        let name = CString::new(format.write_name()).unwrap();
+        match unsafe {
+            carchive::archive_write_set_format_by_name(self.archive_writer, name.as_ptr())
+        } {
             carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
             _ => return Err(Error::from(self.archive_writer)),
         }
 
-        self.file_format = format;
+        self.file_format = Some(format);
         Ok(())
     }
 
-    pub fn set_output_filter(&mut self, filter: c_int) -> Result<()> {
-        match unsafe { carchive::archive_write_add_filter(self.archive_writer, filter) } {
+    pub fn set_output_filter(&mut self, filter: Filter) -> Result<()> {
+        filter.write_support()?;
+        let r = unsafe {
+            match filter {
+                // "none" is not a name known by archive_write_add_filter_by_name
+                Filter::None => carchive::archive_write_add_filter_none(self.archive_writer),
+                _ => {
+                    let name = CString::new(filter.to_string()).unwrap();
+                    carchive::archive_write_add_filter_by_name(self.archive_writer, name.as_ptr())
+                }
+            }
+        };
+
+        match r {
             carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
             _ => return Err(Error::from(self.archive_writer)),
         }
-        self.file_filter = filter;
+        self.file_filter = Some(filter);
         Ok(())
     }
 
@@ -141,14 +231,58 @@ impl<W: Write> ArchiveWriter<W> {
         Ok(())
     }
 
+    /// Encrypts every entry written afterwards. Only zip output supports encryption,
+    /// so the format has to be set first.
+    pub fn set_encryption(&mut self, method: ZipEncryption, passphrase: &str) -> Result<()> {
+        self.set_encryption_method(method)?;
+
+        let p = CString::new(passphrase.to_string()).unwrap();
+        match unsafe { carchive::archive_write_set_passphrase(self.archive_writer, p.as_ptr()) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+            _ => return Err(Error::from(self.archive_writer)),
+        }
+        Ok(())
+    }
+
+    /// Same as `set_encryption` but the passphrase is requested from `func` when needed.
+    pub fn set_encryption_callback<F>(&mut self, method: ZipEncryption, func: F) -> Result<()>
+    where
+        F: FnMut() -> Option<String> + 'static,
+    {
+        self.set_encryption_method(method)?;
+
+        let callback = self
+            .passphrase_callback
+            .insert(Box::new(PassphraseCallback::new(func)));
+        match unsafe {
+            carchive::archive_write_set_passphrase_callback(
+                self.archive_writer,
+                std::ptr::addr_of_mut!(**callback) as *mut c_void,
+                Some(archive_passphrase),
+            )
+        } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+            _ => return Err(Error::from(self.archive_writer)),
+        }
+        Ok(())
+    }
+
+    fn set_encryption_method(&mut self, method: ZipEncryption) -> Result<()> {
+        // unknown format options are only a warning for libarchive, check it here
+        if self.file_format != Some(Format::Zip) {
+            return Err(Error::EncryptionUnsupported);
+        }
+        self.add_format_option("encryption", method.option_value())
+    }
+
     pub fn open(&mut self) -> Result<()> {
-        if self.file_format < 0 || self.file_filter < 0 {
+        if self.file_format.is_none() || self.file_filter.is_none() {
             return Err(Error::IncompleteInitialization);
         }
         match unsafe {
             carchive::archive_write_open(
                 self.archive_writer,
-                std::ptr::addr_of_mut!(*self.fileref) as *mut c_void,
+                std::ptr::addr_of_mut!(**self.fileref.as_mut().unwrap()) as *mut c_void,
                 None,
                 Some(archivewriter_write::<W>),
                 None,
@@ -157,11 +291,45 @@ impl<W: Write> ArchiveWriter<W> {
             carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
             _ => return Err(Error::from(self.archive_writer)),
         }
+        self.links = Some(LinkResolver::new(self.archive_writer));
         Ok(())
     }
 
+    /// Writes the archive trailer, flushes the sink and hands it back. Dropping the
+    /// writer does the same but any error on the way is lost.
+    pub fn finish(mut self) -> Result<(W, WriteStats)> {
+        if let Some(e) = self.short_entry.take() {
+            return Err(e);
+        }
+        self.flush_links()?;
+        match unsafe { carchive::archive_write_close(self.archive_writer) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
+            _ => return Err(Error::from(self.archive_writer)),
+        }
+
+        // filter 0 is the one fed by the format, -1 the last one, writing to the sink
+        let stats = unsafe {
+            WriteStats {
+                entries: carchive::archive_file_count(self.archive_writer) as u64,
+                bytes_in: carchive::archive_filter_bytes(self.archive_writer, 0) as u64,
+                bytes_out: carchive::archive_filter_bytes(self.archive_writer, -1) as u64,
+            }
+        };
+
+        let freed = self.free();
+        self.archive_writer = null_mut();
+        freed?;
+
+        let mut fileref = self.fileref.take().unwrap();
+        fileref.obj.flush()?;
+        Ok((fileref.obj, stats))
+    }
+
     // this free is not meant to called directly. Only by borrow system
     fn free(&mut self) -> Result<()> {
+        if self.archive_writer.is_null() {
+            return Ok(());
+        }
         match unsafe { archive_write_free(self.archive_writer) } {
             carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
             _ => Err(Error::from(self.archive_writer)),
@@ -170,75 +338,36 @@ impl<W: Write> ArchiveWriter<W> {
 
     // this is only for output write filter.
     pub fn set_compression_high(&mut self) -> Result<()> {
-        let mut max_compression_level = match self.file_filter {
-            ARCHIVE_FILTER_BZIP2 => 9,
-            ARCHIVE_FILTER_GZIP => 9,
-            ARCHIVE_FILTER_LRZIP => 9,
-            ARCHIVE_FILTER_XZ => 9,
-            ARCHIVE_FILTER_LZ4 => 9,
-            ARCHIVE_FILTER_LZIP => 9,
-            ARCHIVE_FILTER_ZSTD => 22,
-            ARCHIVE_FILTER_LZMA => 9,
-            ARCHIVE_FILTER_LZOP => 9,
-            _ => -1,
-        };
-
-        if max_compression_level > 0 {
-            self.add_filter_option("compression-level", &max_compression_level.to_string())?;
+        if let Some(level) = self.file_filter.and_then(|f| f.max_compression_level()) {
+            self.add_filter_option("compression-level", &level.to_string())?;
         }
 
-        max_compression_level = match self.file_format {
-            ARCHIVE_FORMAT_7ZIP => 9,
-            ARCHIVE_FORMAT_XAR => 9,
-            ARCHIVE_FORMAT_ZIP => 9,
-            _ => -1,
-        };
-
-        if max_compression_level > 0 {
-            self.add_format_option("compression-level", &max_compression_level.to_string())?;
+        if let Some(level) = self.format_max_compression_level() {
+            self.add_format_option("compression-level", &level.to_string())?;
         }
 
         Ok(())
     }
 
     pub fn set_compression_mid(&mut self) -> Result<()> {
-        let mut max_compression_level = match self.file_filter {
-            ARCHIVE_FILTER_BZIP2 => 9,
-            ARCHIVE_FILTER_GZIP => 9,
-            ARCHIVE_FILTER_LRZIP => 9,
-            ARCHIVE_FILTER_XZ => 9,
-            ARCHIVE_FILTER_LZ4 => 9,
-            ARCHIVE_FILTER_LZIP => 9,
-            ARCHIVE_FILTER_ZSTD => 22,
-            ARCHIVE_FILTER_LZMA => 9,
-            ARCHIVE_FILTER_LZOP => 9,
-            _ => -1,
-        };
-
-        if max_compression_level > 0 {
-            self.add_filter_option(
-                "compression-level",
-                &(max_compression_level / 2).to_string(),
-            )?;
+        if let Some(level) = self.file_filter.and_then(|f| f.max_compression_level()) {
+            self.add_filter_option("compression-level", &(level / 2).to_string())?;
         }
 
-        max_compression_level = match self.file_format {
-            ARCHIVE_FORMAT_7ZIP => 9,
-            ARCHIVE_FORMAT_XAR => 9,
-            ARCHIVE_FORMAT_ZIP => 9,
-            _ => -1,
-        };
-
-        if max_compression_level > 0 {
-            self.add_format_option(
-                "compression-level",
-                &(max_compression_level / 2).to_string(),
-            )?;
+        if let Some(level) = self.format_max_compression_level() {
+            self.add_format_option("compression-level", &(level / 2).to_string())?;
         }
 
         Ok(())
     }
 
+    fn format_max_compression_level(&self) -> Option<u32> {
+        match self.file_format {
+            Some(Format::SevenZip) | Some(Format::Xar) | Some(Format::Zip) => Some(9),
+            _ => None,
+        }
+    }
+
     pub fn set_compression_low(&mut self) -> Result<()> {
         self.add_format_option("compression-level", "0")?;
         self.add_filter_option("compression-level", "0")
@@ -247,92 +376,560 @@ impl<W: Write> ArchiveWriter<W> {
     // Simple Rust API. Nothing else but call new and then set format and add objects
     // it cannot be simpler
     pub fn set_output_targz(&mut self) -> Result<()> {
-        self.set_output_format(ARCHIVE_FORMAT_TAR)?;
-        self.set_output_filter(ARCHIVE_FILTER_GZIP)
+        self.set_output_format(Format::Tar)?;
+        self.set_output_filter(Filter::Gzip)
     }
 
     pub fn set_output_tarxz(&mut self) -> Result<()> {
-        self.set_output_format(ARCHIVE_FORMAT_TAR)?;
-        self.set_output_filter(ARCHIVE_FILTER_XZ)
+        self.set_output_format(Format::Tar)?;
+        self.set_output_filter(Filter::Xz)
     }
 
     pub fn set_output_tarzst(&mut self) -> Result<()> {
-        self.set_output_format(ARCHIVE_FORMAT_TAR)?;
-        self.set_output_filter(ARCHIVE_FILTER_ZSTD)
+        self.set_output_format(Format::Tar)?;
+        self.set_output_filter(Filter::Zstd)
     }
 
     pub fn set_output_7zlzma2(&mut self) -> Result<()> {
-        self.set_output_format(ARCHIVE_FORMAT_7ZIP)?;
-        self.set_output_filter(ARCHIVE_FILTER_NONE)?;
+        self.set_output_format(Format::SevenZip)?;
+        self.set_output_filter(Filter::None)?;
         self.add_format_option("compression", "lzma2")
     }
 
     pub fn set_output_zip(&mut self) -> Result<()> {
-        self.set_output_format(ARCHIVE_FORMAT_ZIP)?;
-        self.set_output_filter(ARCHIVE_FILTER_NONE)?;
+        self.set_output_format(Format::Zip)?;
+        self.set_output_filter(Filter::None)?;
         self.add_format_option("compression", "deflate")
     }
 
+    /// Adds an entry with the data of `source`, which must yield exactly
+    /// `objmeta.size()` bytes. Use `add_obj_from_stream` when the size is unknown.
     pub fn add_obj_from_reader<S: Read>(
         &mut self,
         mut source: S,
         archivepath: &str,
         objmeta: &Metadata,
     ) -> Result<()> {
-        let mut buffer: [u8; BUFFER_SIZE] = [0u8; BUFFER_SIZE];
-        let p = CString::new(archivepath.to_string()).unwrap();
+        let entry = new_entry(archivepath, objmeta, objmeta.nodetype())?;
+        unsafe { archive_entry_set_size(entry.as_ptr(), objmeta.size()) };
+        set_sparse_map(entry.as_ptr(), objmeta.sparse_map());
+        self.write_header(&entry)?;
 
-        unsafe {
-            let entry = archive_entry_new();
-
-            archive_entry_set_size(entry, objmeta.size()); // quick way to get the size?
-                                                         // archive_entry_set_perm(entry, 0o777);
-                                                         // archive_entry_set_filetype(entry, AE_IFREG);
-            archive_entry_set_mode(entry, objmeta.nodetype() | objmeta.perm());
-            archive_entry_set_perm(entry, objmeta.perm());
-            archive_entry_set_ctime(entry, objmeta.ctime(), objmeta.ctime_nano());
-            archive_entry_set_mtime(entry, objmeta.mtime(), objmeta.mtime_nano());
-            archive_entry_set_atime(entry, objmeta.atime(), objmeta.atime_nano());
-            archive_entry_set_pathname(entry, p.as_ptr());
-            archive_entry_set_uid(entry, objmeta.owner());
-            archive_entry_set_gid(entry, objmeta.group());
-
-            match archive_write_header(self.archive_writer, entry) {
+        //write file
+        self.write_sized(&mut source, archivepath, objmeta.size())
+    }
+
+    /// Starts an entry whose data is then written through the returned handle,
+    /// `objmeta.size()` bytes in total.
+    pub fn start_entry(
+        &mut self,
+        archivepath: &str,
+        objmeta: &Metadata,
+    ) -> Result<EntryWriter<'_, W>> {
+        let entry = new_entry(archivepath, objmeta, objmeta.nodetype())?;
+        unsafe { archive_entry_set_size(entry.as_ptr(), objmeta.size()) };
+        set_sparse_map(entry.as_ptr(), objmeta.sparse_map());
+        self.write_header(&entry)?;
+
+        Ok(EntryWriter {
+            writer: self,
+            archivepath: archivepath.to_owned(),
+            size: objmeta.size(),
+            written: 0,
+            finished: false,
+        })
+    }
+
+    /// Adds an entry with all the data of `source`, whatever its length; the size in
+    /// `objmeta` is ignored. Returns the number of bytes stored. Zip stores the data
+    /// as it comes, using data descriptors. Other formats need the size in the
+    /// header, so the data is first spooled to a temporary file.
+    pub fn add_obj_from_stream<S: Read>(
+        &mut self,
+        mut source: S,
+        archivepath: &str,
+        objmeta: &Metadata,
+    ) -> Result<u64> {
+        if self.file_format == Some(Format::Zip) {
+            let entry = new_entry(archivepath, objmeta, AE_IFREG)?;
+            unsafe { carchive::archive_entry_unset_size(entry.as_ptr()) };
+            self.write_header(&entry)?;
+            let written = self.write_data(&mut source)?;
+
+            // the sizes and crc go in the data descriptor, written when the
+            // entry is finished
+            match unsafe { carchive::archive_write_finish_entry(self.archive_writer) } {
                 carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => (),
                 _ => return Err(Error::from(self.archive_writer)),
             }
+            return Ok(written);
+        }
 
-            loop {
-                let readed = source.read(&mut buffer)?;
-                if readed == 0 {
-                    break;
-                }
+        let mut spool = spool_file()?;
+        let size = std::io::copy(&mut source, &mut spool)?;
+        spool.seek(SeekFrom::Start(0))?;
+
+        let mut meta = objmeta.clone();
+        meta.size = size as i64;
+        meta.sparse = Vec::new();
+        self.add_obj_from_reader(spool, archivepath, &meta)?;
+        Ok(size)
+    }
+
+    /// Adds a symlink pointing to `target`. The target is stored as is, it does not
+    /// need to exist.
+    pub fn add_symlink(&mut self, archivepath: &str, target: &str) -> Result<()> {
+        let entry = new_entry(archivepath, &Metadata::new(0o777), AE_IFLNK)?;
+        let t = CString::new(target).unwrap();
+        unsafe { carchive::archive_entry_copy_symlink(entry.as_ptr(), t.as_ptr()) };
+        self.write_header(&entry)
+    }
+
+    /// Adds an empty directory. Its content is added separately.
+    pub fn add_directory(&mut self, archivepath: &str, objmeta: &Metadata) -> Result<()> {
+        let entry = new_entry(archivepath, objmeta, AE_IFDIR)?;
+        self.write_header(&entry)
+    }
+
+    /// Adds a named pipe.
+    pub fn add_fifo(&mut self, archivepath: &str, objmeta: &Metadata) -> Result<()> {
+        let entry = new_entry(archivepath, objmeta, AE_IFIFO)?;
+        self.write_header(&entry)
+    }
+
+    /// Adds a character device node with the given major and minor numbers.
+    pub fn add_char_device(
+        &mut self,
+        archivepath: &str,
+        major: u64,
+        minor: u64,
+        objmeta: &Metadata,
+    ) -> Result<()> {
+        self.add_device_entry(archivepath, AE_IFCHR, major, minor, objmeta)
+    }
+
+    /// Adds a block device node with the given major and minor numbers.
+    pub fn add_block_device(
+        &mut self,
+        archivepath: &str,
+        major: u64,
+        minor: u64,
+        objmeta: &Metadata,
+    ) -> Result<()> {
+        self.add_device_entry(archivepath, AE_IFBLK, major, minor, objmeta)
+    }
+
+    fn add_device_entry(
+        &mut self,
+        archivepath: &str,
+        nodetype: u32,
+        major: u64,
+        minor: u64,
+        objmeta: &Metadata,
+    ) -> Result<()> {
+        let entry = new_entry(archivepath, objmeta, nodetype)?;
+        unsafe {
+            carchive::archive_entry_set_rdevmajor(entry.as_ptr(), major as carchive::dev_t);
+            carchive::archive_entry_set_rdevminor(entry.as_ptr(), minor as carchive::dev_t);
+        }
+        self.write_header(&entry)
+    }
 
-                if archive_write_data(
+    fn write_header(&mut self, entry: &OwnedEntry) -> Result<()> {
+        if let Some(e) = self.short_entry.take() {
+            return Err(e);
+        }
+        match unsafe { archive_write_header(self.archive_writer, entry.as_ptr()) } {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+            // only this entry is refused, the archive can still be written to
+            carchive::ARCHIVE_FAILED => {
+                let pathname =
+                    unsafe { CStr::from_ptr(carchive::archive_entry_pathname(entry.as_ptr())) };
+                let reason = match Error::from(self.archive_writer) {
+                    Error::Extraction(reason) => reason,
+                    other => other.to_string(),
+                };
+                Err(Error::Unstorable(
+                    pathname.to_string_lossy().into_owned(),
+                    reason,
+                ))
+            }
+            _ => Err(Error::from(self.archive_writer)),
+        }
+    }
+
+    // copies exactly size bytes, anything else would leave a corrupted entry
+    fn write_sized<S: Read>(&mut self, source: &mut S, archivepath: &str, size: i64) -> Result<()> {
+        let written = self.write_data(&mut source.by_ref().take(size as u64))?;
+        let mut extra = [0u8; 1];
+        if written != size as u64 || source.read(&mut extra)? != 0 {
+            return Err(Error::SizeMismatch(archivepath.to_owned(), size));
+        }
+        Ok(())
+    }
+
+    fn write_data<S: Read>(&mut self, source: &mut S) -> Result<u64> {
+        let mut buffer: [u8; BUFFER_SIZE] = [0u8; BUFFER_SIZE];
+        let mut written = 0;
+        loop {
+            let readed = source.read(&mut buffer)?;
+            if readed == 0 {
+                break;
+            }
+
+            if unsafe {
+                archive_write_data(
                     self.archive_writer,
                     buffer.as_ptr() as *const c_void,
                     readed,
-                ) != readed as isize
-                {
-                    return Err(Error::from(self.archive_writer));
-                }
+                )
+            } != readed as isize
+            {
+                return Err(Error::from(self.archive_writer));
             }
+            written += readed as u64;
+        }
+        Ok(written)
+    }
+
+    /// Leaves out the files `matcher` excludes from `add_file` and `add_tree`,
+    /// matched with the path they get in the archive. `add_tree` does not walk
+    /// directories matching an exclusion pattern, anything else left out is still
+    /// walked and its content matched entry by entry.
+    pub fn set_matcher(&mut self, matcher: Matcher) {
+        self.matcher = Some(matcher);
+    }
 
-            //write file
-            archive_entry_free(entry);
+    /// Inclusion patterns of the matcher no entry added so far matched.
+    pub fn unmatched_inclusions(&mut self) -> Result<Vec<String>> {
+        match self.matcher.as_mut() {
+            Some(matcher) => matcher.unmatched_inclusions(),
+            None => Ok(Vec::new()),
         }
-        Ok(())
     }
 
+    /// Adds whatever is found at `localpath`, with its extended attributes and ACLs.
+    /// Symlinks are stored as links, not followed, and directories are added
+    /// without their content (see `add_tree`).
     pub fn add_file(&mut self, localpath: &str, archivepath: &str) -> Result<()> {
-        let source = File::open(localpath)?;
-        let meta = source.metadata()?;
-        self.add_obj_from_reader(source, archivepath, &meta.into())
+        let mut disk = DiskReader::new(&TreeOptions::default())?;
+        let entry = disk.entry_from_file(Path::new(localpath))?;
+
+        let p = CString::new(archivepath.to_string()).unwrap();
+        unsafe { carchive::archive_entry_copy_pathname(entry.as_ptr(), p.as_ptr()) };
+        if self.excluded(&entry)? {
+            return Ok(());
+        }
+
+        // the data is read from sourcepath once the link resolver lets the
+        // entry through, which may be later for some formats
+        self.write_linked(entry)
+    }
+
+    /// Recursively adds `local_dir` with everything below it. Entries are stored
+    /// under `archive_prefix`; an empty prefix puts the content of `local_dir`
+    /// at the root of the archive.
+    ///
+    /// Files the format can't store, like sockets in a tar, are skipped and
+    /// returned as `Error::Unstorable`, the rest of the tree is still added.
+    pub fn add_tree<P: AsRef<Path>>(
+        &mut self,
+        local_dir: P,
+        archive_prefix: &str,
+        options: &TreeOptions,
+    ) -> Result<Vec<Error>> {
+        let root = local_dir.as_ref();
+        let mut disk = DiskReader::new(options)?;
+        disk.open(root)?;
+
+        let mut skipped = Vec::new();
+        while let Some(entry) = disk.next_entry()? {
+            // the root itself is not stored with an empty prefix, its content is
+            let stored = rebase_pathname(entry.as_ptr(), root, archive_prefix);
+            if stored && self.pruned(&entry)? {
+                continue;
+            }
+            disk.descend()?;
+            if !stored || self.excluded(&entry)? {
+                continue;
+            }
+            match self.write_linked(entry) {
+                Err(e @ Error::Unstorable(..)) => skipped.push(e),
+                other => other?,
+            }
+        }
+        Ok(skipped)
+    }
+
+    fn pruned(&self, entry: &OwnedEntry) -> Result<bool> {
+        match &self.matcher {
+            Some(matcher) => matcher.pruned(entry.as_ptr()),
+            None => Ok(false),
+        }
+    }
+
+    fn excluded(&self, entry: &OwnedEntry) -> Result<bool> {
+        match &self.matcher {
+            Some(matcher) => matcher.excluded(entry.as_ptr()),
+            None => Ok(false),
+        }
+    }
+
+    // hardlinks of an entry already written are stored as links only
+    fn write_linked(&mut self, entry: OwnedEntry) -> Result<()> {
+        let (first, second) = match self.links.as_mut() {
+            Some(links) => links.linkify(entry),
+            None => (Some(entry), None),
+        };
+
+        for entry in [first, second].into_iter().flatten() {
+            self.write_disk_entry(&entry)?;
+        }
+        Ok(())
+    }
+
+    fn flush_links(&mut self) -> Result<()> {
+        while let Some(entry) = self.links.as_mut().and_then(|links| links.deferred()) {
+            self.write_disk_entry(&entry)?;
+        }
+        Ok(())
+    }
+
+    // writes the entry, with the content of its sourcepath as body. Holes in
+    // the file are recorded in the sparse map and never read.
+    fn write_disk_entry(&mut self, entry: &OwnedEntry) -> Result<()> {
+        let size = unsafe { carchive::archive_entry_size(entry.as_ptr()) };
+        if unsafe { carchive::archive_entry_filetype(entry.as_ptr()) } != AE_IFREG || size <= 0 {
+            return self.write_header(entry);
+        }
+
+        let sourcepath = unsafe { carchive::archive_entry_sourcepath(entry.as_ptr()) };
+        let sourcepath = unsafe { CStr::from_ptr(sourcepath) }.to_bytes();
+        let mut source = File::open(OsStr::from_bytes(sourcepath))?;
+
+        let pathname = unsafe { CStr::from_ptr(carchive::archive_entry_pathname(entry.as_ptr())) };
+        let pathname = pathname.to_string_lossy();
+
+        let mut regions = data_regions(&source, size)?;
+        if regions == [(0, size)] {
+            self.write_header(entry)?;
+            return self.write_sized(&mut source, &pathname, size);
+        }
+
+        // a file made only of a hole still needs a map to be seen as sparse
+        if regions.is_empty() {
+            regions.push((size, 0));
+        }
+        set_sparse_map(entry.as_ptr(), &regions);
+        self.write_header(entry)?;
+
+        // the format drops the zeros given for the holes, formats without
+        // sparse support store them
+        let mut position = 0;
+        for (offset, length) in regions {
+            self.write_zeros(offset - position)?;
+            source.seek(SeekFrom::Start(offset as u64))?;
+            self.write_data(&mut (&mut source).take(length as u64))?;
+            position = offset + length;
+        }
+        self.write_zeros(size - position)
+    }
+
+    // the single entry of a raw archive: its size is unknown up front and the
+    // data is then handed over with write_bytes
+    pub(crate) fn start_raw_entry(&mut self) -> Result<()> {
+        let entry = new_entry("data", &Metadata::new(0o644), AE_IFREG)?;
+        unsafe { carchive::archive_entry_unset_size(entry.as_ptr()) };
+        self.write_header(&entry)
+    }
+
+    pub(crate) fn write_bytes(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = unsafe {
+            archive_write_data(
+                self.archive_writer,
+                buf.as_ptr() as *const c_void,
+                buf.len(),
+            )
+        };
+        if written < 0 {
+            return Err(Error::from(self.archive_writer));
+        }
+        Ok(written as usize)
+    }
+
+    fn write_zeros(&mut self, mut length: i64) -> Result<()> {
+        let zeros = vec![0u8; ZEROS_SIZE];
+        while length > 0 {
+            let chunk = length.min(ZEROS_SIZE as i64) as usize;
+            if unsafe {
+                archive_write_data(self.archive_writer, zeros.as_ptr() as *const c_void, chunk)
+            } != chunk as isize
+            {
+                return Err(Error::from(self.archive_writer));
+            }
+            length -= chunk as i64;
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveWriter<Vec<u8>> {
+    /// Finishes an archive written to memory and returns its bytes.
+    pub fn to_vec(self) -> Result<Vec<u8>> {
+        Ok(self.finish()?.0)
+    }
+}
+
+impl<W: Write> EntryWriter<'_, W> {
+    /// Completes the entry, reporting a size mismatch or a failure of the format
+    /// that dropping would hide.
+    pub fn finish(mut self) -> Result<()> {
+        self.finished = true;
+        let r = unsafe { carchive::archive_write_finish_entry(self.writer.archive_writer) };
+        if self.written != self.size {
+            return Err(Error::SizeMismatch(self.archivepath.clone(), self.size));
+        }
+        match r {
+            carchive::ARCHIVE_OK | carchive::ARCHIVE_WARN => Ok(()),
+            _ => Err(Error::from(self.writer.archive_writer)),
+        }
+    }
+}
+
+impl<W: Write> Write for EntryWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.written + buf.len() as i64 > self.size {
+            return Err(Error::SizeMismatch(self.archivepath.clone(), self.size).into());
+        }
+
+        let written = self.writer.write_bytes(buf)?;
+        self.written += written as i64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
+impl<W: Write> Drop for EntryWriter<'_, W> {
+    fn drop(&mut self) {
+        if !self.finished {
+            unsafe { carchive::archive_write_finish_entry(self.writer.archive_writer) };
+            if self.written != self.size {
+                self.writer.short_entry =
+                    Some(Error::SizeMismatch(self.archivepath.clone(), self.size));
+            }
+        }
+    }
+}
+
+// unnamed temporary file, nothing is left behind whatever happens. O_TMPFILE
+// never gives it a name; where the filesystem lacks it, a name is picked and
+// removed right away. create_new (O_EXCL) refuses anything already there, a
+// symlink planted by someone else included, so a taken name is just skipped.
+fn spool_file() -> Result<File> {
+    let dir = std::env::temp_dir();
+    match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .mode(0o600)
+        .custom_flags(libc::O_TMPFILE)
+        .open(&dir)
+    {
+        Ok(file) => return Ok(file),
+        // no O_TMPFILE support, by the kernel or the filesystem
+        Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => (),
+        Err(e) if e.raw_os_error() == Some(libc::EISDIR) => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let path = dir.join(format!(
+            ".simple-archive-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(file) => {
+                std::fs::remove_file(&path)?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// header only entry, the caller sets the size when there is a body
+fn new_entry(archivepath: &str, objmeta: &Metadata, nodetype: u32) -> Result<OwnedEntry> {
+    let entry = OwnedEntry::new();
+    let p = CString::new(archivepath.to_string()).unwrap();
+
+    unsafe {
+        let e = entry.as_ptr();
+        archive_entry_set_filetype(e, nodetype);
+        archive_entry_set_perm(e, objmeta.perm());
+        archive_entry_set_ctime(e, objmeta.ctime(), objmeta.ctime_nano());
+        archive_entry_set_mtime(e, objmeta.mtime(), objmeta.mtime_nano());
+        archive_entry_set_atime(e, objmeta.atime(), objmeta.atime_nano());
+        archive_entry_set_pathname(e, p.as_ptr());
+        archive_entry_set_uid(e, objmeta.owner());
+        archive_entry_set_gid(e, objmeta.group());
+        carchive::archive_entry_set_nlink(e, objmeta.nlink());
+        carchive::archive_entry_set_dev(e, objmeta.dev());
+        carchive::archive_entry_set_ino64(e, objmeta.ino());
+        carchive::archive_entry_set_rdevmajor(e, objmeta.rdevmajor());
+        carchive::archive_entry_set_rdevminor(e, objmeta.rdevminor());
+        if let (Some(secs), Some(nanos)) = (objmeta.birthtime(), objmeta.birthtime_nano()) {
+            carchive::archive_entry_set_birthtime(e, secs, nanos);
+        }
+
+        if let Some(uname) = objmeta.uname() {
+            let u = CString::new(uname).unwrap();
+            carchive::archive_entry_copy_uname(e, u.as_ptr());
+        }
+        if let Some(gname) = objmeta.gname() {
+            let g = CString::new(gname).unwrap();
+            carchive::archive_entry_copy_gname(e, g.as_ptr());
+        }
+        if let Some(symlink) = objmeta.symlink() {
+            let l = CString::new(symlink).unwrap();
+            carchive::archive_entry_copy_symlink(e, l.as_ptr());
+        }
+        if let Some(hardlink) = objmeta.hardlink() {
+            let h = CString::new(hardlink).unwrap();
+            carchive::archive_entry_copy_hardlink(e, h.as_ptr());
+        }
+
+        for (name, value) in objmeta.xattrs() {
+            let n = CString::new(name.as_str()).unwrap();
+            carchive::archive_entry_xattr_add_entry(
+                e,
+                n.as_ptr(),
+                value.as_ptr() as *const c_void,
+                value.len(),
+            );
+        }
+    }
+    // after the permissions, the owner/group/other entries of an access ACL
+    // overwrite them
+    objmeta.acl().apply(entry.as_ptr())?;
+    Ok(entry)
+}
+
 impl<W: Write> Drop for ArchiveWriter<W> {
     fn drop(&mut self) {
+        if !self.archive_writer.is_null() {
+            drop(self.flush_links());
+        }
         drop(self.free());
     }
 }
@@ -1,6 +1,23 @@
 use std::fs::File;
 
-use simple_archive::writer::ArchiveWriter;
+use simple_archive::{
+    acl::{Acl, AclBrand, AclTag, AclType},
+    format::{Filter, Format},
+    reader::ArchiveReader,
+    tree::{SymlinkPolicy, TreeOptions},
+    writer::ArchiveWriter,
+    Error, Metadata, AE_IFBLK, AE_IFCHR, AE_IFDIR, AE_IFIFO, AE_IFLNK, AE_IFREG,
+};
+
+// the fixture has to come back out of the archive unchanged
+fn assert_stored_random(archive: &str) {
+    let mut r = ArchiveReader::new(File::open(archive).unwrap()).unwrap();
+    r.reader_seek_obj("E/output.xz").unwrap();
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut r, &mut data).unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    assert_eq!(sha256::digest(data), sha256::digest(disk_data));
+}
 
 #[test]
 fn compress_archive_7z() {
@@ -11,6 +28,8 @@ fn compress_archive_7z() {
     a.open().unwrap();
     a.add_file("tests/fixtures/random.txt", "E/output.xz")
         .unwrap();
+    a.finish().unwrap();
+    assert_stored_random("tests/fixtures_out/compressed.7z");
 }
 
 #[test]
@@ -22,6 +41,8 @@ fn compress_archive_zip() {
     a.open().unwrap();
     a.add_file("tests/fixtures/random.txt", "E/output.xz")
         .unwrap();
+    a.finish().unwrap();
+    assert_stored_random("tests/fixtures_out/compressed.zip");
 }
 
 #[test]
@@ -33,6 +54,8 @@ fn compress_archive_targz() {
     a.open().unwrap();
     a.add_file("tests/fixtures/random.txt", "E/output.xz")
         .unwrap();
+    a.finish().unwrap();
+    assert_stored_random("tests/fixtures_out/compressed.tar.gz");
 }
 
 #[test]
@@ -44,6 +67,8 @@ fn compress_archive_tarxz() {
     a.open().unwrap();
     a.add_file("tests/fixtures/random.txt", "E/output.xz")
         .unwrap();
+    a.finish().unwrap();
+    assert_stored_random("tests/fixtures_out/compressed.tar.xz");
 }
 
 #[test]
@@ -55,4 +80,408 @@ fn compress_archive_tarzst() {
     a.open().unwrap();
     a.add_file("tests/fixtures/random.txt", "E/output.xz")
         .unwrap();
+    a.finish().unwrap();
+    assert_stored_random("tests/fixtures_out/compressed.tar.zst");
+}
+
+#[test]
+fn format_filter_names() {
+    assert_eq!("gnutar".parse::<Format>().unwrap(), Format::GnuTar);
+    assert_eq!(Format::SevenZip.to_string(), "7zip");
+    assert_eq!("zstd".parse::<Filter>().unwrap(), Filter::Zstd);
+    assert!(matches!("tgz".parse::<Format>(), Err(Error::UnknownFormat)));
+    assert!(matches!(
+        "brotli".parse::<Filter>(),
+        Err(Error::UnknownFilter)
+    ));
+}
+
+#[test]
+fn compress_read_only_format() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    assert!(matches!(
+        a.set_output_format(Format::Rar),
+        Err(Error::UnknownFormat)
+    ));
+    assert!(matches!(
+        a.set_output_filter(Filter::Rpm),
+        Err(Error::UnknownFilter)
+    ));
+
+    // the writer is still usable with another choice
+    a.set_output_format(Format::Zip).unwrap();
+    a.set_output_filter(Filter::None).unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/test2.txt", "test2.txt").unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut r = ArchiveReader::from_bytes(&data).unwrap();
+    let files = r.list_files().unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].filepath(), "test2.txt");
+}
+
+#[test]
+fn compress_tree() {
+    let root = "tests/fixtures_out/tree_src";
+    let _ = std::fs::remove_dir_all(root);
+    std::fs::create_dir_all(format!("{}/sub", root)).unwrap();
+    std::fs::copy(
+        "tests/fixtures/random.txt",
+        format!("{}/sub/random.txt", root),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink("sub/random.txt", format!("{}/link", root)).unwrap();
+
+    {
+        let dest = File::create("tests/fixtures_out/tree.tar.gz").unwrap();
+        let mut a = ArchiveWriter::new(dest).unwrap();
+        a.set_output_targz().unwrap();
+        a.open().unwrap();
+        let options = TreeOptions::new().symlinks(SymlinkPolicy::Physical);
+        a.add_tree(root, "prefix", &options).unwrap();
+    }
+
+    let source = File::open("tests/fixtures_out/tree.tar.gz").unwrap();
+    let mut r = ArchiveReader::new(source).unwrap();
+    let files = r.list_files().unwrap();
+    let kind = |name: &str| {
+        files
+            .iter()
+            .find(|m| m.filepath().trim_end_matches('/') == name)
+            .map(|m| m.nodetype())
+    };
+    assert_eq!(kind("prefix"), Some(AE_IFDIR));
+    assert_eq!(kind("prefix/sub"), Some(AE_IFDIR));
+    assert_eq!(kind("prefix/sub/random.txt"), Some(AE_IFREG));
+    assert_eq!(kind("prefix/link"), Some(AE_IFLNK));
+
+    r.reader_seek_obj("prefix/sub/random.txt").unwrap();
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut r, &mut data).unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    assert_eq!(sha256::digest(data), sha256::digest(disk_data));
+}
+
+#[test]
+fn compress_finish() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+    let (data, stats) = a.finish().unwrap();
+
+    assert_eq!(stats.entries(), 1);
+    assert_eq!(stats.bytes_out(), data.len() as u64);
+    // the tar data, a whole number of blocks before compression
+    assert!(stats.bytes_in() > 0);
+    assert_eq!(stats.bytes_in() % 512, 0);
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let files = r.list_files().unwrap();
+    assert_eq!(files[0].filepath(), "random.txt");
+}
+
+#[test]
+fn compress_special_entries() {
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    let link = "tests/fixtures_out/special_link";
+    let _ = std::fs::remove_file(link);
+    std::os::unix::fs::symlink("../fixtures/random.txt", link).unwrap();
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_directory("dev", &Metadata::new(0o755)).unwrap();
+    a.add_char_device("dev/null", 1, 3, &Metadata::new(0o666))
+        .unwrap();
+    a.add_block_device("dev/loop0", 7, 0, &Metadata::new(0o660))
+        .unwrap();
+    a.add_fifo("run/initctl", &Metadata::new(0o600)).unwrap();
+    a.add_symlink("bin", "usr/bin").unwrap();
+    a.add_file(link, "from_disk").unwrap();
+    let (data, _) = a.finish().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let files = r.list_files().unwrap();
+    let kind = |name: &str| {
+        files
+            .iter()
+            .find(|m| m.filepath().trim_end_matches('/') == name)
+            .map(|m| m.nodetype())
+    };
+    assert_eq!(kind("dev"), Some(AE_IFDIR));
+    assert_eq!(kind("dev/null"), Some(AE_IFCHR));
+    assert_eq!(kind("dev/loop0"), Some(AE_IFBLK));
+    assert_eq!(kind("run/initctl"), Some(AE_IFIFO));
+    assert_eq!(kind("bin"), Some(AE_IFLNK));
+    assert_eq!(kind("from_disk"), Some(AE_IFLNK));
+}
+
+#[test]
+fn compress_tree_hardlinks() {
+    let root = "tests/fixtures_out/hardlink_src";
+    let _ = std::fs::remove_dir_all(root);
+    std::fs::create_dir_all(root).unwrap();
+    std::fs::copy("tests/fixtures/random.txt", format!("{}/a.txt", root)).unwrap();
+    std::fs::hard_link(format!("{}/a.txt", root), format!("{}/b.txt", root)).unwrap();
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_tree(root, "", &TreeOptions::default()).unwrap();
+    let (data, _) = a.finish().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let mut sizes: Vec<i64> = r
+        .list_files()
+        .unwrap()
+        .iter()
+        .filter(|m| m.filepath().ends_with(".txt"))
+        .map(|m| m.size())
+        .collect();
+    sizes.sort();
+    let disk_size = std::fs::metadata("tests/fixtures/random.txt")
+        .unwrap()
+        .len() as i64;
+    // the data is only stored once, the second name is a hardlink entry
+    assert_eq!(sizes, vec![0, disk_size]);
+}
+
+#[test]
+fn compress_tree_socket() {
+    let root = "tests/fixtures_out/socket_src";
+    let _ = std::fs::remove_dir_all(root);
+    std::fs::create_dir_all(root).unwrap();
+    std::fs::copy("tests/fixtures/test2.txt", format!("{}/a.txt", root)).unwrap();
+    let _socket = std::os::unix::net::UnixListener::bind(format!("{}/s.sock", root)).unwrap();
+    std::fs::copy("tests/fixtures/test2.txt", format!("{}/z.txt", root)).unwrap();
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_format(Format::Pax).unwrap();
+    a.set_output_filter(Filter::None).unwrap();
+    a.open().unwrap();
+    let skipped = a.add_tree(root, "", &TreeOptions::default()).unwrap();
+    assert!(matches!(&skipped[..], [Error::Unstorable(path, _)] if path == "s.sock"));
+    let (data, _) = a.finish().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let mut names: Vec<String> = r
+        .list_files()
+        .unwrap()
+        .iter()
+        .map(|m| m.filepath().to_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a.txt", "z.txt"]);
+}
+
+#[test]
+fn compress_metadata_roundtrip() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_symlink("bin", "usr/bin").unwrap();
+    a.add_char_device("null", 1, 3, &Metadata::new(0o666))
+        .unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+    let (data, _) = a.finish().unwrap();
+
+    // copy every entry into a new archive through the public API
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let mut b = ArchiveWriter::new(Vec::new()).unwrap();
+    b.set_output_targz().unwrap();
+    b.open().unwrap();
+    let mut entries = r.entries();
+    while let Some(entry) = entries.next_entry() {
+        let entry = entry.unwrap();
+        let meta = entry.metadata().clone();
+        b.add_obj_from_reader(entry, meta.filepath(), &meta)
+            .unwrap();
+    }
+    let (data, _) = b.finish().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let files = r.list_files().unwrap();
+    let find = |name: &str| files.iter().find(|m| m.filepath() == name).unwrap();
+    assert_eq!(find("bin").symlink(), Some("usr/bin"));
+    assert_eq!(find("null").nodetype(), AE_IFCHR);
+    assert_eq!(find("null").rdevmajor(), 1);
+    assert_eq!(find("null").rdevminor(), 3);
+    let disk_meta = std::fs::metadata("tests/fixtures/random.txt").unwrap();
+    assert_eq!(find("random.txt").size() as u64, disk_meta.len());
+}
+
+#[test]
+fn compress_acl_roundtrip() {
+    let text = "user::rw-\nuser:1000:r--\ngroup::r--\nmask::r--\nother::---";
+    let acl = Acl::from_text(text, AclBrand::Posix1e).unwrap();
+    let extra = acl
+        .entries()
+        .iter()
+        .find(|e| e.tag == AclTag::User)
+        .unwrap();
+    assert_eq!(extra.acl_type, AclType::Access);
+    assert_eq!(extra.qualifier, Some(1000));
+
+    let mut meta = Metadata::new(0o640);
+    meta.set_acl(acl.clone());
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_obj_from_reader(std::io::empty(), "acl.txt", &meta)
+        .unwrap();
+    let (data, _) = a.finish().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let files = r.list_files().unwrap();
+    assert_eq!(files[0].acl().to_text().unwrap(), acl.to_text().unwrap());
+
+    assert!(matches!(
+        Acl::from_text("user:bogus", AclBrand::Posix1e),
+        Err(Error::InvalidAcl(_))
+    ));
+}
+
+#[test]
+fn compress_sparse_file() {
+    use std::io::{Seek, SeekFrom, Write};
+
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    let path = "tests/fixtures_out/sparse.img";
+    let size = 4 << 20;
+    {
+        let mut f = File::create(path).unwrap();
+        f.set_len(size).unwrap();
+        f.seek(SeekFrom::Start(2 << 20)).unwrap();
+        f.write_all(b"not a hole").unwrap();
+    }
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_file(path, "sparse.img").unwrap();
+    let (data, _) = a.finish().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let mut entries = r.entries();
+    let mut entry = entries.next_entry().unwrap().unwrap();
+    assert_eq!(entry.metadata().size(), size as i64);
+
+    let mut rebuilt = vec![0u8; size as usize];
+    let mut stored = 0;
+    while let Some(block) = entry.read_block().unwrap() {
+        let start = block.offset() as usize;
+        rebuilt[start..start + block.data().len()].copy_from_slice(block.data());
+        stored += block.data().len();
+    }
+    assert_eq!(rebuilt, std::fs::read(path).unwrap());
+
+    // only filesystems with hole support produce a sparse entry
+    if !entry.metadata().sparse_map().is_empty() {
+        assert!(stored < size as usize);
+    }
+}
+
+#[test]
+fn compress_unknown_size() {
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+
+    for zip in [true, false] {
+        let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+        if zip {
+            a.set_output_zip().unwrap();
+        } else {
+            a.set_output_targz().unwrap();
+        }
+        a.open().unwrap();
+        // the declared size is ignored
+        let stored = a
+            .add_obj_from_stream(&disk_data[..], "streamed.txt", &Metadata::new(0o644))
+            .unwrap();
+        assert_eq!(stored, disk_data.len() as u64);
+        let (data, _) = a.finish().unwrap();
+
+        let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+        r.reader_seek_obj("streamed.txt").unwrap();
+        let mut v = Vec::new();
+        std::io::Read::read_to_end(&mut r, &mut v).unwrap();
+        assert_eq!(v, disk_data);
+    }
+}
+
+#[test]
+fn compress_size_mismatch() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    // Metadata::new declares an empty entry
+    match a.add_obj_from_reader(&b"unexpected"[..], "short.txt", &Metadata::new(0o644)) {
+        Err(Error::SizeMismatch(path, size)) => {
+            assert_eq!(path, "short.txt");
+            assert_eq!(size, 0);
+        }
+        other => panic!("size mismatch not detected: {:?}", other),
+    }
+}
+
+#[test]
+fn compress_entry_writer() {
+    use std::io::Write;
+
+    let mut meta = Metadata::new(0o644);
+    meta.set_size(11);
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    let mut entry = a.start_entry("written.txt", &meta).unwrap();
+    write!(entry, "hello ").unwrap();
+    entry.write_all(b"world").unwrap();
+    entry.finish().unwrap();
+    let (data, _) = a.finish().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    r.reader_seek_obj("written.txt").unwrap();
+    let mut v = String::new();
+    std::io::Read::read_to_string(&mut r, &mut v).unwrap();
+    assert_eq!(v, "hello world");
+}
+
+#[test]
+fn compress_entry_writer_mismatch() {
+    use std::io::Write;
+
+    let mut meta = Metadata::new(0o644);
+    meta.set_size(11);
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+
+    let mut entry = a.start_entry("short.txt", &meta).unwrap();
+    entry.write_all(b"hello").unwrap();
+    assert!(matches!(entry.finish(), Err(Error::SizeMismatch(_, 11))));
+
+    let mut entry = a.start_entry("long.txt", &meta).unwrap();
+    assert!(entry.write_all(b"hello world, again").is_err());
+    entry.write_all(b"hello world").unwrap();
+    entry.finish().unwrap();
+
+    // dropping a short entry is reported by what comes next
+    let mut entry = a.start_entry("dropped.txt", &meta).unwrap();
+    entry.write_all(b"hello").unwrap();
+    drop(entry);
+    assert!(matches!(
+        a.add_directory("dir", &Metadata::new(0o755)),
+        Err(Error::SizeMismatch(path, 11)) if path == "dropped.txt"
+    ));
+    a.add_directory("dir", &Metadata::new(0o755)).unwrap();
+
+    let mut entry = a.start_entry("dropped.txt", &meta).unwrap();
+    entry.write_all(b"hello").unwrap();
+    drop(entry);
+    assert!(matches!(a.finish(), Err(Error::SizeMismatch(_, 11))));
 }
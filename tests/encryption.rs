@@ -0,0 +1,122 @@
+use std::{fs::File, io::Read};
+
+use simple_archive::{
+    reader::ArchiveReader,
+    writer::{ArchiveWriter, ZipEncryption},
+    Error,
+};
+
+fn write_encrypted(path: &str, method: ZipEncryption) {
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    let dest = File::create(path).unwrap();
+    let mut a = ArchiveWriter::new(dest).unwrap();
+    a.set_output_zip().unwrap();
+    a.set_encryption(method, "s3cr3t").unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+}
+
+#[test]
+fn encrypted_zip_roundtrip() {
+    write_encrypted("tests/fixtures_out/encrypted.zip", ZipEncryption::Aes256);
+
+    let source = File::open("tests/fixtures_out/encrypted.zip").unwrap();
+    let mut a = ArchiveReader::builder()
+        .passphrase("wrong")
+        .passphrase("s3cr3t")
+        .open(source)
+        .unwrap();
+    let meta = a.next().unwrap().unwrap();
+    assert!(meta.is_encrypted());
+
+    let mut v = vec![];
+    a.read_to_end(&mut v).unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    if sha256::digest(v) != sha256::digest(disk_data) {
+        panic!("wrong decrypted data")
+    }
+}
+
+#[test]
+fn encrypted_zip_passphrase_callback() {
+    write_encrypted("tests/fixtures_out/zipcrypt.zip", ZipEncryption::ZipCrypt);
+
+    let source = File::open("tests/fixtures_out/zipcrypt.zip").unwrap();
+    let mut asked = 0;
+    let mut a = ArchiveReader::builder()
+        .passphrase_callback(move || {
+            asked += 1;
+            (asked == 1).then(|| "s3cr3t".to_owned())
+        })
+        .open(source)
+        .unwrap();
+    let mut entries = a.entries();
+    let mut entry = entries.next_entry().unwrap().unwrap();
+    let mut v = vec![];
+    entry.read_to_end(&mut v).unwrap();
+    assert_eq!(v.len() as i64, entry.metadata().size());
+}
+
+#[test]
+fn encrypted_zip_missing_passphrase() {
+    write_encrypted("tests/fixtures_out/nopass.zip", ZipEncryption::Aes128);
+
+    let source = File::open("tests/fixtures_out/nopass.zip").unwrap();
+    let mut a = ArchiveReader::new(source).unwrap();
+    a.next().unwrap().unwrap();
+
+    let mut v = vec![];
+    let err = a.read_to_end(&mut v).unwrap_err();
+    match err.into_inner().unwrap().downcast::<Error>() {
+        Ok(e) if matches!(*e, Error::Passphrase(_)) => (),
+        other => panic!("expected a passphrase error, got {:?}", other),
+    }
+}
+
+fn read_error(data: &[u8], passphrase: &str) -> Error {
+    let mut a = ArchiveReader::builder()
+        .passphrase(passphrase)
+        .open_bytes(data)
+        .unwrap();
+    a.next().unwrap().unwrap();
+
+    let mut v = vec![];
+    let err = a.read_to_end(&mut v).unwrap_err();
+    *err.into_inner().unwrap().downcast::<Error>().unwrap()
+}
+
+#[test]
+fn encrypted_zip_wrong_passphrase() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_zip().unwrap();
+    a.set_encryption(ZipEncryption::Aes256, "s3cr3t").unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+    let mut data = a.to_vec().unwrap();
+
+    let err = read_error(&data, "wrong");
+    assert!(matches!(err, Error::Passphrase(_)), "{:?}", err);
+
+    // damaged data is not blamed on the passphrase
+    let name_len = u16::from_le_bytes([data[26], data[27]]) as usize;
+    let extra_len = u16::from_le_bytes([data[28], data[29]]) as usize;
+    let body = 30 + name_len + extra_len;
+    // after the salt and the password verifier
+    data[body + 18 + 100] ^= 0xff;
+    let err = read_error(&data, "s3cr3t");
+    assert!(!matches!(err, Error::Passphrase(_)), "{:?}", err);
+}
+
+#[test]
+fn encryption_needs_zip() {
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    let dest = File::create("tests/fixtures_out/encrypted.tar.gz").unwrap();
+    let mut a = ArchiveWriter::new(dest).unwrap();
+    a.set_output_targz().unwrap();
+    assert!(matches!(
+        a.set_encryption(ZipEncryption::Aes256, "s3cr3t"),
+        Err(Error::EncryptionUnsupported)
+    ));
+}
@@ -0,0 +1,227 @@
+use std::fs::File;
+
+use simple_archive::{
+    extract::ExtractOptions, reader::ArchiveReader, tree::TreeOptions, writer::ArchiveWriter, Error,
+};
+
+#[test]
+fn extract_whole_archive() {
+    let source = File::open("tests/fixtures/single_file.tar.bz2").unwrap();
+    let mut a = ArchiveReader::new(source).unwrap();
+    a.extract_to("tests/fixtures_out/extract", &ExtractOptions::default())
+        .unwrap();
+
+    let extracted = std::fs::read("tests/fixtures_out/extract/random.txt").unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    if sha256::digest(extracted) != sha256::digest(disk_data) {
+        panic!("wrong extracted data")
+    }
+    assert!(std::path::Path::new("tests/fixtures_out/extract/test2.txt").exists());
+}
+
+#[test]
+fn extract_links_and_modes() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let root = "tests/fixtures_out/links_src";
+    let _ = std::fs::remove_dir_all(root);
+    std::fs::create_dir_all(format!("{}/dir", root)).unwrap();
+    std::fs::copy("tests/fixtures/random.txt", format!("{}/dir/a.txt", root)).unwrap();
+    std::fs::hard_link(format!("{}/dir/a.txt", root), format!("{}/dir/b.txt", root)).unwrap();
+    std::os::unix::fs::symlink("a.txt", format!("{}/dir/link", root)).unwrap();
+    let mode = std::fs::Permissions::from_mode(0o750);
+    std::fs::set_permissions(format!("{}/dir", root), mode).unwrap();
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_tree(root, "", &TreeOptions::default()).unwrap();
+    let (data, _) = a.finish().unwrap();
+
+    let dest = "tests/fixtures_out/links";
+    let _ = std::fs::remove_dir_all(dest);
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    r.extract_to(dest, &ExtractOptions::default()).unwrap();
+
+    let link = std::fs::read_link(format!("{}/dir/link", dest)).unwrap();
+    assert_eq!(link, std::path::Path::new("a.txt"));
+
+    let a = std::fs::metadata(format!("{}/dir/a.txt", dest)).unwrap();
+    let b = std::fs::metadata(format!("{}/dir/b.txt", dest)).unwrap();
+    assert_eq!(a.ino(), b.ino());
+    assert_eq!(a.nlink(), 2);
+
+    // directory modes are only set when the extraction is closed
+    let dir = std::fs::metadata(format!("{}/dir", dest)).unwrap();
+    assert_eq!(dir.mode() & 0o7777, 0o750);
+}
+
+#[test]
+fn extract_refuses_dotdot() {
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    {
+        let dest = File::create("tests/fixtures_out/dotdot.tar.gz").unwrap();
+        let mut a = ArchiveWriter::new(dest).unwrap();
+        a.set_output_targz().unwrap();
+        a.open().unwrap();
+        let source = File::open("tests/fixtures/random.txt").unwrap();
+        let meta = source.metadata().unwrap();
+        a.add_obj_from_reader(source, "../escaped.txt", &meta.into())
+            .unwrap();
+    }
+
+    let source = File::open("tests/fixtures_out/dotdot.tar.gz").unwrap();
+    let mut a = ArchiveReader::new(source).unwrap();
+    match a.extract_to("tests/fixtures_out/dotdot", &ExtractOptions::default()) {
+        Err(Error::UnsafePath(path)) => assert_eq!(path, "../escaped.txt"),
+        other => panic!("unsafe entry was not refused: {:?}", other),
+    }
+    assert!(!std::path::Path::new("tests/fixtures_out/escaped.txt").exists());
+}
+
+fn set_xattr(path: &str, name: &str, value: &[u8]) -> bool {
+    let p = std::ffi::CString::new(path).unwrap();
+    let n = std::ffi::CString::new(name).unwrap();
+    unsafe {
+        libc::setxattr(
+            p.as_ptr(),
+            n.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        ) == 0
+    }
+}
+
+#[test]
+fn extract_xattrs() {
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    let source = "tests/fixtures_out/xattr_src.txt";
+    std::fs::write(source, b"attributes").unwrap();
+    if !set_xattr(source, "user.simple_archive", b"value") {
+        // the filesystem has no user xattrs, nothing to test
+        return;
+    }
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_file(source, "xattr.txt").unwrap();
+    let (data, _) = a.finish().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data.clone())).unwrap();
+    let files = r.list_files().unwrap();
+    assert!(files[0]
+        .xattrs()
+        .contains(&("user.simple_archive".to_owned(), b"value".to_vec())));
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let options = ExtractOptions::new().xattrs(true);
+    r.extract_to("tests/fixtures_out/xattr", &options).unwrap();
+
+    let p = std::ffi::CString::new("tests/fixtures_out/xattr/xattr.txt").unwrap();
+    let n = std::ffi::CString::new("user.simple_archive").unwrap();
+    let mut buffer = [0u8; 16];
+    let size = unsafe {
+        libc::getxattr(
+            p.as_ptr(),
+            n.as_ptr(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+        )
+    };
+    assert_eq!(&buffer[..size as usize], b"value");
+}
+
+// ustar entry written by hand, the writer has no way to store unsafe paths
+fn tar_entry(out: &mut Vec<u8>, name: &str, typeflag: u8, linkname: &str, data: &[u8]) {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = typeflag;
+    header[157..157 + linkname.len()].copy_from_slice(linkname.as_bytes());
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[148..156].copy_from_slice(b"        ");
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+
+    out.extend_from_slice(&header);
+    out.extend_from_slice(data);
+    out.resize(out.len().next_multiple_of(512), 0);
+}
+
+fn tar_end(mut out: Vec<u8>) -> Vec<u8> {
+    out.resize(out.len() + 1024, 0);
+    out
+}
+
+#[test]
+fn extract_absolute_path() {
+    std::fs::create_dir_all("tests/fixtures_out/absolute").unwrap();
+    let target = std::fs::canonicalize("tests/fixtures_out/absolute")
+        .unwrap()
+        .join("abs.txt");
+    let _ = std::fs::remove_file(&target);
+
+    let mut data = Vec::new();
+    tar_entry(&mut data, target.to_str().unwrap(), b'0', "", b"absolute");
+    let data = tar_end(data);
+
+    let mut a = ArchiveReader::new(std::io::Cursor::new(data.clone())).unwrap();
+    match a.extract_to(
+        "tests/fixtures_out/absolute_dest",
+        &ExtractOptions::default(),
+    ) {
+        Err(Error::UnsafePath(path)) => assert_eq!(path, target.to_str().unwrap()),
+        other => panic!("absolute entry was not refused: {:?}", other),
+    }
+    assert!(!target.exists());
+
+    let mut a = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let options = ExtractOptions::new().secure_noabsolutepaths(false);
+    a.extract_to("tests/fixtures_out/absolute_dest", &options)
+        .unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), b"absolute");
+}
+
+#[test]
+fn extract_refuses_hardlink_escape() {
+    for linkname in ["../escaped_link.txt", "/etc/passwd"] {
+        let mut data = Vec::new();
+        tar_entry(&mut data, "link.txt", b'1', linkname, b"");
+        let data = tar_end(data);
+
+        let mut a = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+        match a.extract_to(
+            "tests/fixtures_out/hardlink_escape",
+            &ExtractOptions::default(),
+        ) {
+            Err(Error::UnsafePath(path)) => assert_eq!(path, linkname),
+            other => panic!("hardlink to {} was not refused: {:?}", linkname, other),
+        }
+        assert!(!std::path::Path::new("tests/fixtures_out/hardlink_escape/link.txt").exists());
+    }
+}
+
+#[test]
+fn extract_refuses_symlinked_parent() {
+    let dest = "tests/fixtures_out/symlinked_parent";
+    let _ = std::fs::remove_dir_all(dest);
+    std::fs::create_dir_all(format!("{}/target", dest)).unwrap();
+    std::os::unix::fs::symlink("target", format!("{}/sub", dest)).unwrap();
+
+    let mut data = Vec::new();
+    tar_entry(&mut data, "sub/file.txt", b'0', "", b"through a symlink");
+    let data = tar_end(data);
+
+    let mut a = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    match a.extract_to(dest, &ExtractOptions::default()) {
+        Err(Error::UnsafePath(path)) => assert_eq!(path, "sub/file.txt"),
+        other => panic!("symlinked parent was not refused: {:?}", other),
+    }
+    assert!(!std::path::Path::new(&format!("{}/target/file.txt", dest)).exists());
+}
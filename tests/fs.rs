@@ -0,0 +1,81 @@
+use std::io::{Cursor, Read};
+
+use simple_archive::{fs::ArchiveFs, writer::ArchiveWriter, Metadata, AE_IFDIR, AE_IFLNK};
+
+fn bundle() -> Vec<u8> {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_tarzst().unwrap();
+    a.open().unwrap();
+    for (path, data) in [
+        ("./site/index.html", &b"<html></html>"[..]),
+        ("./site/css/style.css", &b"body {}"[..]),
+        ("config.toml", &b"debug = true"[..]),
+    ] {
+        let mut meta = Metadata::new(0o644);
+        meta.set_size(data.len() as i64);
+        a.add_obj_from_reader(data, path, &meta).unwrap();
+    }
+    a.add_directory("empty/", &Metadata::new(0o700)).unwrap();
+    a.add_symlink("site/home.html", "index.html").unwrap();
+    a.to_vec().unwrap()
+}
+
+fn names(fs: &ArchiveFs<Cursor<Vec<u8>>>, path: &str) -> Vec<String> {
+    fs.read_dir(path)
+        .unwrap()
+        .iter()
+        .map(|m| m.filepath().to_owned())
+        .collect()
+}
+
+#[test]
+fn fs_tree() {
+    let fs = ArchiveFs::new(Cursor::new(bundle())).unwrap();
+    assert_eq!(names(&fs, "/"), ["config.toml", "empty", "site"]);
+    assert_eq!(
+        names(&fs, "site"),
+        ["site/css", "site/home.html", "site/index.html"]
+    );
+    assert_eq!(names(&fs, "./site/css/"), ["site/css/style.css"]);
+    assert!(names(&fs, "empty").is_empty());
+
+    // implicit directories are made up, stored ones keep their metadata
+    assert_eq!(fs.metadata("site").unwrap().nodetype(), AE_IFDIR);
+    assert_eq!(fs.metadata("empty").unwrap().perm(), 0o700);
+    assert_eq!(fs.metadata("site/home.html").unwrap().nodetype(), AE_IFLNK);
+    assert_eq!(fs.metadata("config.toml").unwrap().size(), 12);
+
+    assert!(fs.exists("site/css"));
+    assert!(!fs.exists("site/missing"));
+    assert!(fs.metadata("site/missing").is_err());
+    assert!(fs.read_dir("config.toml").is_err());
+}
+
+#[test]
+fn fs_open() {
+    let mut fs = ArchiveFs::new(Cursor::new(bundle())).unwrap();
+
+    let mut data = String::new();
+    fs.open("config.toml")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "debug = true");
+
+    let mut data = String::new();
+    fs.open("site/home.html")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "<html></html>");
+
+    let mut data = String::new();
+    fs.open("/site/css/style.css")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "body {}");
+
+    assert!(fs.open("site").is_err());
+    assert!(fs.open("nothing").is_err());
+}
@@ -0,0 +1,303 @@
+use std::{
+    io::Cursor,
+    os::unix::fs::PermissionsExt,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use simple_archive::{
+    extract::ExtractOptions,
+    matcher::{Matcher, TimeComparison, TimeField},
+    reader::ArchiveReader,
+    tree::TreeOptions,
+    writer::ArchiveWriter,
+    Metadata,
+};
+
+fn project() -> Vec<u8> {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    for path in [
+        "src/main.rs",
+        "src/io/mod.rs",
+        "src/notes.txt",
+        "build.log",
+        "README.md",
+    ] {
+        let mut meta = Metadata::new(0o644);
+        meta.set_size(path.len() as i64);
+        a.add_obj_from_reader(path.as_bytes(), path, &meta).unwrap();
+    }
+    a.to_vec().unwrap()
+}
+
+fn paths(r: &mut ArchiveReader<Cursor<Vec<u8>>>) -> Vec<String> {
+    r.list_files()
+        .unwrap()
+        .iter()
+        .map(|m| m.filepath().to_owned())
+        .collect()
+}
+
+#[test]
+fn matcher_include_exclude() {
+    let mut m = Matcher::new().unwrap();
+    m.include("src/*.rs").unwrap();
+    m.include("docs").unwrap();
+    m.exclude("*/io").unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(project()))
+        .unwrap();
+    assert_eq!(paths(&mut r), ["src/main.rs"]);
+    assert_eq!(r.unmatched_inclusions().unwrap(), ["docs"]);
+
+    let mut m = Matcher::new().unwrap();
+    m.exclude("*.log").unwrap();
+    m.exclude("README.md").unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(project()))
+        .unwrap();
+    assert_eq!(
+        paths(&mut r),
+        ["src/main.rs", "src/io/mod.rs", "src/notes.txt"]
+    );
+    assert!(r.unmatched_inclusions().unwrap().is_empty());
+}
+
+#[test]
+fn matcher_extract() {
+    let dest = "tests/fixtures_out/matcher_extract";
+    let _ = std::fs::remove_dir_all(dest);
+
+    let mut m = Matcher::new().unwrap();
+    m.include("src").unwrap();
+    m.exclude("*.txt").unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(project()))
+        .unwrap();
+    r.extract_to(dest, &ExtractOptions::default()).unwrap();
+
+    let root = std::path::Path::new(dest);
+    assert!(root.join("src/main.rs").exists());
+    assert!(root.join("src/io/mod.rs").exists());
+    assert!(!root.join("src/notes.txt").exists());
+    assert!(!root.join("build.log").exists());
+}
+
+#[test]
+fn matcher_tree() {
+    let root = "tests/fixtures_out/matcher_tree";
+    let _ = std::fs::remove_dir_all(root);
+    std::fs::create_dir_all(format!("{}/logs", root)).unwrap();
+    std::fs::write(format!("{}/app.conf", root), b"conf").unwrap();
+    std::fs::write(format!("{}/logs/app.log", root), b"log").unwrap();
+    std::fs::write(format!("{}/logs/keep.txt", root), b"keep").unwrap();
+    // never walked, so it does not matter that it can't be read
+    std::fs::create_dir_all(format!("{}/cache/private", root)).unwrap();
+    std::fs::write(format!("{}/cache/private/data", root), b"cache").unwrap();
+    let mode = std::fs::Permissions::from_mode(0o000);
+    std::fs::set_permissions(format!("{}/cache/private", root), mode).unwrap();
+
+    let mut m = Matcher::new().unwrap();
+    m.exclude("*.log").unwrap();
+    m.exclude("cache").unwrap();
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.set_matcher(m);
+    a.open().unwrap();
+    a.add_tree(root, "", &TreeOptions::default()).unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.log")
+        .unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut r = ArchiveReader::new(Cursor::new(data)).unwrap();
+    let mut files: Vec<_> = paths(&mut r)
+        .into_iter()
+        .map(|p| p.trim_end_matches('/').to_owned())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["app.conf", "logs", "logs/keep.txt"]);
+
+    let mut m = Matcher::new().unwrap();
+    m.include("app.conf").unwrap();
+    m.include("docs").unwrap();
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    assert!(a.unmatched_inclusions().unwrap().is_empty());
+    a.set_matcher(m);
+    a.open().unwrap();
+    a.add_tree(format!("{}/logs", root), "", &TreeOptions::default())
+        .unwrap();
+    a.add_file(&format!("{}/app.conf", root), "app.conf")
+        .unwrap();
+    assert_eq!(a.unmatched_inclusions().unwrap(), ["docs"]);
+    let mut r = ArchiveReader::new(Cursor::new(a.to_vec().unwrap())).unwrap();
+    assert_eq!(paths(&mut r), ["app.conf"]);
+
+    let mode = std::fs::Permissions::from_mode(0o755);
+    std::fs::set_permissions(format!("{}/cache/private", root), mode).unwrap();
+}
+
+// a tree with an old and a recent log, archived with everything. The times are
+// whole seconds, the tar.gz keeps nothing finer.
+fn logs(root: &str) -> Vec<u8> {
+    let _ = std::fs::remove_dir_all(root);
+    std::fs::create_dir_all(root).unwrap();
+    let old = std::fs::File::create(format!("{}/old.log", root)).unwrap();
+    old.set_modified(UNIX_EPOCH + Duration::from_secs(946684800))
+        .unwrap();
+    std::fs::write(format!("{}/new.log", root), b"new").unwrap();
+    let new = std::fs::File::options()
+        .write(true)
+        .open(format!("{}/new.log", root))
+        .unwrap();
+    new.set_modified(UNIX_EPOCH + Duration::from_secs(1577836800))
+        .unwrap();
+
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_tree(root, "", &TreeOptions::default()).unwrap();
+    a.to_vec().unwrap()
+}
+
+#[test]
+fn matcher_time() {
+    let root = "tests/fixtures_out/matcher_time";
+    let data = logs(root);
+
+    let mut m = Matcher::new().unwrap();
+    m.include_date(TimeField::Mtime, TimeComparison::Newer, "2010-01-01 UTC")
+        .unwrap();
+    m.include("*.log").unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data.clone()))
+        .unwrap();
+    assert_eq!(paths(&mut r), ["new.log"]);
+
+    let mut m = Matcher::new().unwrap();
+    m.include_file_time(
+        TimeField::Mtime,
+        TimeComparison::Older,
+        format!("{}/new.log", root),
+    )
+    .unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data))
+        .unwrap();
+    assert_eq!(paths(&mut r), ["old.log"]);
+
+    // only what changed since the last run gets archived
+    let mut m = Matcher::new().unwrap();
+    m.include_time(TimeField::Mtime, TimeComparison::NewerOrEqual, 978307200, 0)
+        .unwrap();
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.set_matcher(m);
+    a.open().unwrap();
+    a.add_tree(root, "", &TreeOptions::default()).unwrap();
+    let mut r = ArchiveReader::new(Cursor::new(a.to_vec().unwrap())).unwrap();
+    assert_eq!(paths(&mut r), ["new.log"]);
+
+    // entries described by the caller, not found by a walk
+    let old = std::fs::File::open(format!("{}/old.log", root)).unwrap();
+    let meta = old.metadata().unwrap().into();
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_obj_from_reader(old, "old.txt", &meta).unwrap();
+    a.add_obj_from_reader(std::io::empty(), "now.txt", &Metadata::new(0o644))
+        .unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut m = Matcher::new().unwrap();
+    m.include_date(TimeField::Mtime, TimeComparison::Older, "2010-01-01 UTC")
+        .unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data))
+        .unwrap();
+    assert_eq!(paths(&mut r), ["old.txt"]);
+}
+
+#[test]
+fn matcher_owner() {
+    let root = "tests/fixtures_out/matcher_owner";
+    let data = logs(root);
+    let uid = unsafe { libc::getuid() };
+
+    let mut m = Matcher::new().unwrap();
+    m.include_uid(uid).unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data.clone()))
+        .unwrap();
+    assert_eq!(r.list_files().unwrap().len(), 2);
+
+    let mut m = Matcher::new().unwrap();
+    m.include_uname("no-such-user-here").unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data.clone()))
+        .unwrap();
+    assert!(r.list_files().unwrap().is_empty());
+
+    let mut m = Matcher::new().unwrap();
+    m.include_gid(unsafe { libc::getgid() }).unwrap();
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.set_matcher(m);
+    a.open().unwrap();
+    a.add_tree(root, "", &TreeOptions::default()).unwrap();
+    let mut r = ArchiveReader::new(Cursor::new(a.to_vec().unwrap())).unwrap();
+    assert_eq!(r.list_files().unwrap().len(), 2);
+
+    // any value of a kind matches, but every kind set has to
+    let mut m = Matcher::new().unwrap();
+    m.include_uid(uid).unwrap();
+    m.include_uid(uid + 1).unwrap();
+    m.include_gid(unsafe { libc::getgid() }).unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data.clone()))
+        .unwrap();
+    assert_eq!(r.list_files().unwrap().len(), 2);
+
+    let mut m = Matcher::new().unwrap();
+    m.include_uid(uid).unwrap();
+    m.include_gname("no-such-group-here").unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data.clone()))
+        .unwrap();
+    assert!(r.list_files().unwrap().is_empty());
+
+    // Metadata::new entries belong to root
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_obj_from_reader(std::io::empty(), "root.txt", &Metadata::new(0o644))
+        .unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut m = Matcher::new().unwrap();
+    m.include_uid(0).unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data.clone()))
+        .unwrap();
+    assert_eq!(paths(&mut r), ["root.txt"]);
+
+    let mut m = Matcher::new().unwrap();
+    m.include_gid(1).unwrap();
+    let mut r = ArchiveReader::builder()
+        .matcher(m)
+        .open(Cursor::new(data))
+        .unwrap();
+    assert!(paths(&mut r).is_empty());
+}
@@ -0,0 +1,48 @@
+use std::io::{Read, Write};
+
+use simple_archive::{
+    format::Filter,
+    stream::{Compressor, Decompressor},
+};
+
+fn roundtrip(filter: Filter) {
+    let data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    let mut c = Compressor::new(Vec::new(), filter).unwrap();
+    c.write_all(&data).unwrap();
+    let compressed = c.finish().unwrap();
+    assert_ne!(compressed, data);
+
+    let mut d = Decompressor::new(compressed.as_slice()).unwrap();
+    assert_eq!(d.filters().unwrap()[0].filter(), Some(filter));
+    let mut out = Vec::new();
+    d.read_to_end(&mut out).unwrap();
+    assert_eq!(sha256::digest(out), sha256::digest(data));
+}
+
+#[test]
+fn stream_roundtrip() {
+    for filter in [Filter::Gzip, Filter::Bzip2, Filter::Xz, Filter::Zstd] {
+        roundtrip(filter);
+    }
+}
+
+#[test]
+fn stream_gzip_level() {
+    let mut c = Compressor::with_level(Vec::new(), Filter::Gzip, 9).unwrap();
+    c.write_all(b"hello stream").unwrap();
+    let compressed = c.finish().unwrap();
+    assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+}
+
+#[test]
+fn stream_uncompressed_passthrough() {
+    let mut d = Decompressor::new(&b"plain text"[..]).unwrap();
+    let mut out = Vec::new();
+    d.read_to_end(&mut out).unwrap();
+    assert_eq!(out, b"plain text");
+
+    let mut d = Decompressor::new(&b""[..]).unwrap();
+    let mut out = Vec::new();
+    d.read_to_end(&mut out).unwrap();
+    assert!(out.is_empty());
+}
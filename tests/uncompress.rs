@@ -1,7 +1,14 @@
 use core::panic;
 use std::{fs::File, io::Read};
 
-use simple_archive::reader::ArchiveReader;
+use simple_archive::{
+    format::{Filter, Format},
+    fs::ArchiveFs,
+    index::ArchiveIndex,
+    reader::ArchiveReader,
+    writer::ArchiveWriter,
+    Error, Metadata,
+};
 
 #[test]
 fn seek_to_obj() {
@@ -35,7 +42,7 @@ fn iter_obj() {
     if r.is_none() {
         panic!("no file was returned");
     }
-    let r = r.unwrap();
+    let r = r.unwrap().unwrap();
 
     let mut v = vec![];
     a.read_to_end(&mut v).unwrap();
@@ -48,3 +55,437 @@ fn iter_obj() {
         panic!("wrong uncompressed data")
     }
 }
+
+#[test]
+fn entries_obj() {
+    let source = File::open("tests/fixtures/single_file.tar.bz2").unwrap();
+    let mut a = ArchiveReader::new(source).unwrap();
+    let mut entries = a.entries();
+    let mut count = 0;
+    while let Some(entry) = entries.next_entry() {
+        let mut entry = entry.unwrap();
+        let mut v = vec![];
+        entry.read_to_end(&mut v).unwrap();
+        assert_eq!(v.len() as i64, entry.metadata().size());
+
+        if entry.metadata().filepath() == "random.txt" {
+            let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+            if sha256::digest(v) != sha256::digest(disk_data) {
+                panic!("wrong uncompressed data")
+            }
+        }
+        count += 1;
+    }
+    assert_eq!(count, 2);
+}
+
+#[test]
+fn list_files_obj() {
+    let source = File::open("tests/fixtures/single_file.tar.bz2").unwrap();
+    let mut a = ArchiveReader::new(source).unwrap();
+    let files = a.list_files().unwrap();
+    assert_eq!(files.len(), 2);
+    assert!(a.next().is_none());
+}
+
+#[test]
+fn streaming_obj() {
+    let data = std::fs::read("tests/fixtures/single_file.tar.bz2").unwrap();
+    // a plain reader without Seek, as a pipe would be
+    let source: &[u8] = &data;
+    let mut a = ArchiveReader::new_streaming(source).unwrap();
+    let r = a.next().unwrap().unwrap();
+    assert_eq!(r.filepath(), "random.txt");
+
+    let mut v = vec![];
+    a.read_to_end(&mut v).unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    if sha256::digest(v) != sha256::digest(disk_data) {
+        panic!("wrong uncompressed data")
+    }
+}
+
+#[test]
+fn streaming_needs_seek() {
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    {
+        let dest = File::create("tests/fixtures_out/streaming.7z").unwrap();
+        let mut a = ArchiveWriter::new(dest).unwrap();
+        a.set_output_7zlzma2().unwrap();
+        a.open().unwrap();
+        a.add_file("tests/fixtures/random.txt", "random.txt")
+            .unwrap();
+    }
+
+    let data = std::fs::read("tests/fixtures_out/streaming.7z").unwrap();
+    let source: &[u8] = &data;
+    let mut a = ArchiveReader::new_streaming(source).unwrap();
+    // the header may still be read, the data never is
+    let result = match a.next() {
+        Some(Ok(_)) => a.read_block().map(|_| ()),
+        Some(Err(e)) => Err(e),
+        None => panic!("no entry in 7z"),
+    };
+    match result {
+        Err(Error::SeekRequired(_)) => (),
+        _ => panic!("7z must not be readable from a stream"),
+    }
+}
+
+#[test]
+fn streaming_skips_large_entry() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_format(Format::Pax).unwrap();
+    a.set_output_filter(Filter::None).unwrap();
+    a.open().unwrap();
+    let mut meta = Metadata::new(0o644);
+    meta.set_size(200 * 1024);
+    a.add_obj_from_reader(std::io::repeat(7).take(200 * 1024), "big.bin", &meta)
+        .unwrap();
+    a.add_file("tests/fixtures/test2.txt", "test2.txt").unwrap();
+    let data = a.to_vec().unwrap();
+
+    // the data of big.bin is skipped by seeking forward, which a stream reads
+    let source: &[u8] = &data;
+    let mut r = ArchiveReader::new_streaming(source).unwrap();
+    assert_eq!(r.next().unwrap().unwrap().filepath(), "big.bin");
+    assert_eq!(r.next().unwrap().unwrap().filepath(), "test2.txt");
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    assert_eq!(v, std::fs::read("tests/fixtures/test2.txt").unwrap());
+}
+
+#[test]
+fn streaming_prefixed_zip() {
+    let mut data = b"#!/bin/sh\nexit 0\n".to_vec();
+    {
+        let mut a = ArchiveWriter::new(&mut data).unwrap();
+        a.set_output_zip().unwrap();
+        a.open().unwrap();
+        a.add_file("tests/fixtures/random.txt", "random.txt")
+            .unwrap();
+    }
+
+    // found through the central directory only, like a self-extracting archive
+    let source: &[u8] = &data;
+    assert!(matches!(
+        ArchiveReader::new_streaming(source),
+        Err(Error::UnsupportedFormat(_))
+    ));
+
+    let mut a = ArchiveReader::new(std::io::Cursor::new(&data)).unwrap();
+    let entry = a.next().unwrap().unwrap();
+    assert_eq!(entry.filepath(), "random.txt");
+}
+
+#[test]
+fn detected_format() {
+    let source = File::open("tests/fixtures/single_file.tar.bz2").unwrap();
+    let mut a = ArchiveReader::new(source).unwrap();
+    assert!(matches!(a.format(), Err(Error::UnknownFormat)));
+
+    a.next().unwrap().unwrap();
+    // despite its name the fixture is a plain GNU tar
+    let format = a.format().unwrap();
+    assert_eq!(format.format(), Some(Format::GnuTar));
+
+    let filters = a.filters().unwrap();
+    assert_eq!(filters.len(), 1);
+    assert_eq!(filters[0].filter(), Some(Filter::None));
+
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    {
+        let dest = File::create("tests/fixtures_out/detected.tar.bz2").unwrap();
+        let mut a = ArchiveWriter::new(dest).unwrap();
+        a.set_output_format(Format::GnuTar).unwrap();
+        a.set_output_filter(Filter::Bzip2).unwrap();
+        a.open().unwrap();
+        a.add_file("tests/fixtures/random.txt", "random.txt")
+            .unwrap();
+    }
+
+    let source = File::open("tests/fixtures_out/detected.tar.bz2").unwrap();
+    let mut a = ArchiveReader::new(source).unwrap();
+    a.next().unwrap().unwrap();
+    assert_eq!(a.format().unwrap().format(), Some(Format::GnuTar));
+
+    let filters = a.filters().unwrap();
+    assert_eq!(filters[0].filter(), Some(Filter::Bzip2));
+    assert_eq!(filters[0].code(), simple_archive::ARCHIVE_FILTER_BZIP2);
+    assert_eq!(filters[0].name(), "bzip2");
+}
+
+#[test]
+fn detected_filter_chain() {
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    {
+        let dest = File::create("tests/fixtures_out/detected.tar.xz").unwrap();
+        let mut a = ArchiveWriter::new(dest).unwrap();
+        a.set_output_format(Format::Ustar).unwrap();
+        a.set_output_filter(Filter::Xz).unwrap();
+        a.open().unwrap();
+        a.add_file("tests/fixtures/random.txt", "random.txt")
+            .unwrap();
+    }
+
+    let source = File::open("tests/fixtures_out/detected.tar.xz").unwrap();
+    let mut a = ArchiveReader::new(source).unwrap();
+    a.next().unwrap().unwrap();
+    assert_eq!(a.format().unwrap().format(), Some(Format::Ustar));
+
+    let filters: Vec<_> = a.filters().unwrap().iter().map(|f| f.filter()).collect();
+    assert_eq!(filters, [Some(Filter::Xz), Some(Filter::None)]);
+    assert_eq!(a.filters().unwrap()[0].name(), "xz");
+}
+
+#[test]
+fn restricted_formats() {
+    let source = File::open("tests/fixtures/single_file.tar.bz2").unwrap();
+    let mut a = ArchiveReader::builder()
+        .formats([Format::Tar, Format::Zip])
+        .filters([Filter::Bzip2])
+        .open(source)
+        .unwrap();
+    assert_eq!(a.list_files().unwrap().len(), 2);
+
+    let source = File::open("tests/fixtures/single_file.tar.bz2").unwrap();
+    let r = ArchiveReader::builder().formats([Format::Zip]).open(source);
+    assert!(matches!(r, Err(Error::UnsupportedFormat(_))));
+
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    {
+        let dest = File::create("tests/fixtures_out/restricted.tar.gz").unwrap();
+        let mut a = ArchiveWriter::new(dest).unwrap();
+        a.set_output_targz().unwrap();
+        a.open().unwrap();
+        a.add_file("tests/fixtures/random.txt", "random.txt")
+            .unwrap();
+    }
+
+    let source = File::open("tests/fixtures_out/restricted.tar.gz").unwrap();
+    let r = ArchiveReader::builder()
+        .formats([Format::Tar])
+        .filters([Filter::Bzip2, Filter::Zstd])
+        .open(source);
+    assert!(matches!(r, Err(Error::UnsupportedFormat(_))));
+
+    let source = File::open("tests/fixtures_out/restricted.tar.gz").unwrap();
+    let mut a = ArchiveReader::builder()
+        .formats([Format::Tar])
+        .filters([Filter::Gzip, Filter::Zstd])
+        .open(source)
+        .unwrap();
+    assert_eq!(a.list_files().unwrap().len(), 1);
+
+    // uncompressed data never goes through a filter
+    let source = File::open("tests/fixtures/single_file.tar.bz2").unwrap();
+    let mut a = ArchiveReader::builder()
+        .formats([Format::Tar])
+        .filters([Filter::Gzip, Filter::Zstd])
+        .open(source)
+        .unwrap();
+    assert_eq!(a.list_files().unwrap().len(), 2);
+}
+
+#[test]
+fn memory_roundtrip() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    // 7z needs to seek, which memory reading supports
+    a.set_output_7zlzma2().unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut r = ArchiveReader::from_bytes(&data).unwrap();
+    let mut entries = r.entries();
+    let mut entry = entries.next_entry().unwrap().unwrap();
+    assert_eq!(entry.metadata().filepath(), "random.txt");
+    let mut v = Vec::new();
+    entry.read_to_end(&mut v).unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    assert_eq!(sha256::digest(v), sha256::digest(disk_data));
+}
+
+#[test]
+fn memory_index() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_format(Format::Pax).unwrap();
+    a.set_output_filter(Filter::None).unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+    a.add_file("tests/fixtures/test2.txt", "test2.txt").unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut r = ArchiveReader::from_bytes(&data).unwrap();
+    let index = r.build_index().unwrap();
+    assert!(index.is_direct());
+    r.seek_index(&index, "test2.txt").unwrap();
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    assert_eq!(v, std::fs::read("tests/fixtures/test2.txt").unwrap());
+
+    r.reader_seek_obj("random.txt").unwrap();
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    assert_eq!(sha256::digest(v), sha256::digest(disk_data));
+
+    let mut fs = ArchiveFs::from_reader(ArchiveReader::from_bytes(&data).unwrap()).unwrap();
+    let mut v = Vec::new();
+    fs.open("test2.txt").unwrap().read_to_end(&mut v).unwrap();
+    assert_eq!(v, std::fs::read("tests/fixtures/test2.txt").unwrap());
+}
+
+#[test]
+fn index_direct_seek() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_format(Format::Pax).unwrap();
+    a.set_output_filter(Filter::None).unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+    a.add_file("tests/fixtures/test2.txt", "dir/test2.txt")
+        .unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data.clone())).unwrap();
+    let index = r.build_index().unwrap();
+    assert!(index.is_direct());
+    assert_eq!(
+        index.paths().collect::<Vec<_>>(),
+        ["random.txt", "dir/test2.txt"]
+    );
+    assert_eq!(index.position("random.txt"), Some(0));
+    assert!(index.position("dir/test2.txt") > Some(0));
+
+    std::fs::create_dir_all("tests/fixtures_out").unwrap();
+    index.save("tests/fixtures_out/direct.index").unwrap();
+    let index = ArchiveIndex::load("tests/fixtures_out/direct.index").unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    r.seek_index(&index, "dir/test2.txt").unwrap();
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    assert_eq!(v, std::fs::read("tests/fixtures/test2.txt").unwrap());
+
+    r.seek_index(&index, "random.txt").unwrap();
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    assert_eq!(sha256::digest(v), sha256::digest(disk_data));
+}
+
+#[test]
+fn index_compressed_seek() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/test2.txt", "test2.txt").unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut a = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let index = a.build_index().unwrap();
+    assert!(!index.is_direct());
+
+    let mut buffer = Vec::new();
+    index.write_to(&mut buffer).unwrap();
+    assert_eq!(ArchiveIndex::read_from(buffer.as_slice()).unwrap(), index);
+
+    a.seek_index(&index, "random.txt").unwrap();
+    let mut v = Vec::new();
+    a.read_to_end(&mut v).unwrap();
+    let disk_data = std::fs::read("tests/fixtures/random.txt").unwrap();
+    assert_eq!(sha256::digest(v), sha256::digest(disk_data));
+
+    assert!(a.seek_index(&index, "missing.txt").is_err());
+    assert!(matches!(
+        ArchiveIndex::read_from(&buffer[..buffer.len() - 1]),
+        Err(Error::InvalidIndex(_))
+    ));
+
+    // a path stored twice is read from its last copy, like when extracting
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_targz().unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/random.txt", "dup.txt").unwrap();
+    a.add_file("tests/fixtures/test2.txt", "dup.txt").unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut a = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let index = a.build_index().unwrap();
+    assert!(!index.is_direct());
+    a.seek_index(&index, "dup.txt").unwrap();
+    let mut v = Vec::new();
+    a.read_to_end(&mut v).unwrap();
+    assert_eq!(v, std::fs::read("tests/fixtures/test2.txt").unwrap());
+}
+
+#[test]
+fn index_seek_past_large_entry() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_format(Format::Pax).unwrap();
+    a.set_output_filter(Filter::None).unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/test2.txt", "first.txt").unwrap();
+    let mut meta = Metadata::new(0o644);
+    meta.set_size(200 * 1024);
+    a.add_obj_from_reader(std::io::repeat(7).take(200 * 1024), "big.bin", &meta)
+        .unwrap();
+    a.add_file("tests/fixtures/test2.txt", "test2.txt").unwrap();
+    let data = a.to_vec().unwrap();
+
+    let mut r = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+    let index = r.build_index().unwrap();
+    assert!(index.is_direct());
+
+    // libarchive skips the unread entry with seeks counted from the jump
+    r.seek_index(&index, "big.bin").unwrap();
+    assert_eq!(r.next().unwrap().unwrap().filepath(), "test2.txt");
+    let mut v = Vec::new();
+    r.read_to_end(&mut v).unwrap();
+    assert_eq!(v, std::fs::read("tests/fixtures/test2.txt").unwrap());
+}
+
+#[test]
+fn index_zip_seek() {
+    let mut a = ArchiveWriter::new(Vec::new()).unwrap();
+    a.set_output_zip().unwrap();
+    a.open().unwrap();
+    a.add_file("tests/fixtures/random.txt", "random.txt")
+        .unwrap();
+    a.add_file("tests/fixtures/test2.txt", "dir/test2.txt")
+        .unwrap();
+    a.add_file("tests/fixtures/test2.txt", "random.txt")
+        .unwrap();
+    let zip = a.to_vec().unwrap();
+
+    // a self-extracting stub in front moves every entry
+    let mut prefixed = vec![0u8; 1000];
+    prefixed.extend_from_slice(&zip);
+
+    for data in [zip, prefixed] {
+        let mut a = ArchiveReader::new(std::io::Cursor::new(data)).unwrap();
+        let index = a.build_index().unwrap();
+        assert_eq!(index.format(), Some(Format::Zip));
+        assert!(index.is_direct());
+
+        let mut buffer = Vec::new();
+        index.write_to(&mut buffer).unwrap();
+        let index = ArchiveIndex::read_from(buffer.as_slice()).unwrap();
+        assert!(index.is_direct());
+
+        a.seek_index(&index, "dir/test2.txt").unwrap();
+        let mut v = Vec::new();
+        a.read_to_end(&mut v).unwrap();
+        assert_eq!(v, std::fs::read("tests/fixtures/test2.txt").unwrap());
+
+        a.seek_index(&index, "random.txt").unwrap();
+        let mut v = Vec::new();
+        a.read_to_end(&mut v).unwrap();
+        assert_eq!(v, std::fs::read("tests/fixtures/test2.txt").unwrap());
+    }
+}